@@ -33,7 +33,7 @@ fn main() {
     let storage = Default::default();
     let mut program = ComputeExample { compute, storage };
 
-    program.add_module("compute", wgpu::include_wgsl!("compute.wgsl"));
+    program.add_module("compute", wgpu::include_wgsl!("compute.wgsl")).unwrap();
 
     program.add_buffer(
         "example_buffer", 
@@ -51,7 +51,7 @@ fn main() {
     {
         let bind_groups = &["example_bind_group"];
         let push_constant_ranges = &[];
-        program.add_compute_pipelines("compute", bind_groups, &[ComputeKernel { label: "compute", entry_point: "compute" }], push_constant_ranges, None);
+        program.add_compute_pipelines("compute", bind_groups, &[ComputeKernel { label: "compute", entry_point: "compute", constants: &[] }], push_constant_ranges, None).unwrap();
     }
     
     // Write data to GPU
@@ -70,16 +70,8 @@ fn main() {
         label: None
     });
 
-    {
-        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-            label: None,
-            timestamp_writes: None
-        });
-
-        cpass.set_pipeline(&program.storage().compute_pipelines["compute"]);
-        cpass.set_bind_group(0, &program.storage().bind_groups["example_bind_group"], &[]);
-        cpass.dispatch_workgroups(8, 1, 1);
-    }
+    // 128 invocations with a workgroup size of 16 dispatches 8 workgroups
+    program.dispatch(&mut encoder, "compute", &["example_bind_group"], 128).unwrap();
 
     // To send data back to the CPU, we need to copy it into a staging buffer
     program.copy_buffer_to_staging(&mut encoder, "example_buffer");
@@ -100,8 +92,8 @@ fn main() {
         &mut output[..]
     );
 
-    for i in 0..128 {
-        print!("{} ", output[i]);
-        assert_eq!(output[i], (i as u32) * 2);
+    for (i, value) in output.iter().enumerate() {
+        print!("{} ", value);
+        assert_eq!(*value, (i as u32) * 2);
     }
 }
\ No newline at end of file
@@ -38,7 +38,7 @@ fn main() {
     
     let mut program = WindowExample { compute, surface, storage };
 
-    program.add_module("window", wgpu::include_wgsl!("window.wgsl"));
+    program.add_module("window", wgpu::include_wgsl!("window.wgsl")).unwrap();
 
     let swapchain_capabilities = program.surface.get_capabilities(&program.compute().adapter);
     let swapchain_format = swapchain_capabilities.formats[0];
@@ -0,0 +1,125 @@
+use std::borrow::Cow;
+
+use bytemuck::Pod;
+use ndarray::{Array, ArrayView, Dimension, IxDyn};
+use wgpu::util::TextureDataOrder;
+
+use crate::{texture, ComputeProgram, Error};
+
+/// The elements of `array` in row-major order, copied only if its layout is not already
+/// standard, e.g. for transposed or sliced views.
+fn elements<'a, T: Pod, D: Dimension>(array: &ArrayView<'a, T, D>) -> Cow<'a, [T]> {
+    match array.to_slice() {
+        Some(slice) => Cow::Borrowed(slice),
+        None => Cow::Owned(array.iter().copied().collect())
+    }
+}
+
+fn shape_error(label: &'static str, message: String) -> Error {
+    Error::Shape { label, message }
+}
+
+/// Shapes `data` as an array of dimension `D`, using `shape` if it holds exactly
+/// `data.len()` elements and a flat shape otherwise.
+pub(crate) fn to_array<T: Pod, D: Dimension>(label: &'static str, shape: Option<Vec<usize>>, data: Vec<T>) -> Result<Array<T, D>, Error> {
+    let shape = match shape {
+        Some(shape) if shape.iter().product::<usize>() == data.len() => shape,
+        _ => vec![data.len()]
+    };
+
+    let array = Array::from_shape_vec(IxDyn(&shape), data).map_err(|error| shape_error(label, error.to_string()))?;
+
+    array.into_dimensionality::<D>().map_err(|_| shape_error(label, format!(
+        "shape {:?} has {} dimensions, but {} were requested",
+        shape, shape.len(), D::NDIM.map_or("any number of".into(), |ndim| ndim.to_string())
+    )))
+}
+
+pub(crate) fn create_buffer<P: ComputeProgram + ?Sized, T: Pod, D: Dimension>(
+    program: &mut P,
+    label: &'static str,
+    usage: wgpu::BufferUsages,
+    array: ArrayView<T, D>
+) {
+    program.add_buffer_init(label, usage, &elements(&array));
+    program.storage_mut().buffer_shapes.insert(label, array.shape().to_vec());
+}
+
+/// Creates a 2D texture from a `(height, width)` array or a 3D texture from a
+/// `(depth, height, width)` array, with one element per texel.
+pub(crate) fn create_texture<P: ComputeProgram + ?Sized, T: Pod, D: Dimension>(
+    program: &mut P,
+    label: &'static str,
+    usage: wgpu::TextureUsages,
+    format: wgpu::TextureFormat,
+    array: ArrayView<T, D>
+) -> Result<(), Error> {
+    let (dimension, size) = match *array.shape() {
+        [height, width] => (wgpu::TextureDimension::D2, (width, height, 1)),
+        [depth, height, width] => (wgpu::TextureDimension::D3, (width, height, depth)),
+        ref shape => return Err(shape_error(label, format!("textures need 2 or 3 dimensions, found shape {:?}", shape)))
+    };
+
+    if format.block_dimensions() != (1, 1) || format.block_copy_size(None) != Some(std::mem::size_of::<T>() as u32) {
+        return Err(Error::Texture {
+            label,
+            message: format!("`{}` is not the size of one {:?} texel", std::any::type_name::<T>(), format)
+        });
+    }
+
+    let texture = program.compute().device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size: wgpu::Extent3d { width: size.0 as u32, height: size.1 as u32, depth_or_array_layers: size.2 as u32 },
+        usage: usage | wgpu::TextureUsages::COPY_DST,
+        format,
+        dimension,
+        mip_level_count: 1,
+        sample_count: 1,
+        view_formats: &[]
+    });
+
+    let data = elements(&array);
+    texture::write_all(&program.compute().queue, label, &texture, TextureDataOrder::LayerMajor, bytemuck::cast_slice(&data))?;
+
+    program.storage_mut().insert_texture(label, texture, false);
+    Ok(())
+}
+
+/// Reads every layer or depth slice of one mip into a `(height, width)` array for
+/// single-layer 2D textures or a `(layers, height, width)` array otherwise.
+pub(crate) fn read_texture<P: ComputeProgram + ?Sized, T: Pod, D: Dimension>(
+    program: &P,
+    label: &'static str,
+    mip: u32
+) -> Result<Array<T, D>, Error> {
+    let texture = &program.storage().textures[label];
+    let layout = texture::ImageLayout::of_mip(label, texture, mip)?;
+
+    let layers = match texture.dimension() {
+        wgpu::TextureDimension::D3 => (texture.depth_or_array_layers() >> mip).max(1),
+        _ => texture.depth_or_array_layers()
+    };
+
+    let (width, height) = (layout.width as usize, layout.height as usize);
+    let mut data = Vec::with_capacity(layers as usize * width * height);
+
+    for layer in 0..layers {
+        let texels = program.read_texture::<T>(label, mip, layer)?;
+
+        if texels.len() != width * height {
+            return Err(Error::Texture {
+                label,
+                message: format!("`{}` is not the size of one {:?} texel", std::any::type_name::<T>(), texture.format())
+            });
+        }
+
+        data.extend(texels);
+    }
+
+    let shape = match (texture.dimension(), layers) {
+        (wgpu::TextureDimension::D3, _) | (_, 2..) => vec![layers as usize, height, width],
+        _ => vec![height, width]
+    };
+
+    to_array(label, Some(shape), data)
+}
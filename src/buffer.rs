@@ -0,0 +1,77 @@
+use std::{any::TypeId, ops::Range};
+
+use bytemuck::Pod;
+
+use crate::{Error, Storage};
+
+/// The element type and count of a buffer created with `add_buffer_init` or
+/// `add_buffer_zeroed_for`, used to check typed reads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferElements {
+    pub type_id: TypeId,
+    pub type_name: &'static str,
+    pub size: usize,
+    pub count: usize
+}
+
+impl BufferElements {
+    pub fn of<T: Pod>(count: usize) -> Self {
+        Self {
+            type_id: TypeId::of::<T>(),
+            type_name: std::any::type_name::<T>(),
+            size: std::mem::size_of::<T>(),
+            count
+        }
+    }
+}
+
+/// Returns how many `T`s a buffer holds, failing if it was created for another type.
+pub(crate) fn element_count<T: Pod>(storage: &Storage, label: &'static str) -> Result<usize, Error> {
+    match storage.buffer_elements.get(label) {
+        Some(elements) if elements.type_id != TypeId::of::<T>() => Err(Error::ElementType {
+            label,
+            expected: elements.type_name,
+            found: std::any::type_name::<T>()
+        }),
+        Some(elements) => Ok(elements.count),
+        None => Ok(storage.buffers[label].size() as usize / std::mem::size_of::<T>())
+    }
+}
+
+/// Checks that `range` lies within a buffer of `size` bytes, that its start is a multiple
+/// of `offset_alignment` and that its length is a multiple of `COPY_BUFFER_ALIGNMENT`.
+pub(crate) fn check_range(label: &'static str, range: &Range<u64>, size: u64, offset_alignment: u64) -> Result<(), Error> {
+    let error = |message: String| Err(Error::Range { label, message });
+
+    if range.start > range.end || range.end > size {
+        return error(format!("{:?} is out of bounds for a buffer of {} bytes", range, size));
+    }
+
+    if !range.start.is_multiple_of(offset_alignment) {
+        return error(format!("offset {} is not a multiple of {}", range.start, offset_alignment));
+    }
+
+    if !(range.end - range.start).is_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT) {
+        return error(format!("size {} is not a multiple of {}", range.end - range.start, wgpu::COPY_BUFFER_ALIGNMENT));
+    }
+
+    Ok(())
+}
+
+/// The length of a mapped range that covers `size` bytes, since mapped ranges must be a
+/// multiple of `COPY_BUFFER_ALIGNMENT` long.
+pub(crate) fn aligned_size(size: u64) -> u64 {
+    size.next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT)
+}
+
+/// Copies `dst.len()` elements from a mapped buffer, starting `offset` bytes in. The
+/// mapped range is widened to an aligned length and the padding after `dst` is dropped.
+pub(crate) fn read_mapped<T: Pod>(buffer: &wgpu::Buffer, offset: u64, dst: &mut [T]) {
+    let num_bytes = std::mem::size_of_val(dst);
+    if num_bytes == 0 {
+        return;
+    }
+
+    let data = buffer.slice(offset..offset + aligned_size(num_bytes as u64)).get_mapped_range();
+    bytemuck::cast_slice_mut(dst).copy_from_slice(&data[..num_bytes]);
+}
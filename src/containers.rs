@@ -0,0 +1,384 @@
+use wgpu::{util::TextureDataOrder, TextureFormat};
+#[cfg(feature = "ktx2")]
+use wgpu::{AstcBlock, AstcChannel};
+
+use crate::{texture, ComputeProgram, Error};
+
+/// A texture read from a KTX2 or DDS file, ready to be uploaded with `texture::write_all`.
+pub(crate) struct TextureFile {
+    pub format: TextureFormat,
+    pub dimension: wgpu::TextureDimension,
+    pub size: wgpu::Extent3d,
+    pub mip_level_count: u32,
+    /// Whether the layers are cube faces, six per cube
+    pub cube: bool,
+    pub order: TextureDataOrder,
+    pub data: Vec<u8>
+}
+
+impl TextureFile {
+    /// Fails with a message naming the missing feature if the device cannot sample
+    /// textures of this format, e.g. BC formats on most mobile GPUs. The file is not
+    /// decompressed on the CPU.
+    pub fn check_features(&self, label: &'static str, device: &wgpu::Device) -> Result<(), Error> {
+        let required = self.format.required_features();
+
+        if !device.features().contains(required) {
+            return Err(Error::Texture {
+                label,
+                message: format!(
+                    "{:?} needs {:?}, which the device was not created with; request it in `Compute::new` or convert the file to a format the device supports",
+                    self.format, required - device.features()
+                )
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Creates a texture holding the contents of a file, viewed as a cube map if it has cube
+/// faces.
+pub(crate) fn create_texture<P: ComputeProgram + ?Sized>(
+    program: &mut P,
+    label: &'static str,
+    usage: wgpu::TextureUsages,
+    file: TextureFile
+) -> Result<(), Error> {
+    let device = program.compute().device.clone();
+    file.check_features(label, &device)?;
+
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size: file.size,
+        usage: usage | wgpu::TextureUsages::COPY_DST,
+        format: file.format,
+        dimension: file.dimension,
+        mip_level_count: file.mip_level_count,
+        sample_count: 1,
+        view_formats: &[]
+    });
+
+    texture::write_all(&program.compute().queue, label, &texture, file.order, &file.data)?;
+
+    program.storage_mut().insert_texture(label, texture, file.cube);
+    Ok(())
+}
+
+fn texture_error(label: &'static str, message: String) -> Error {
+    Error::Texture { label, message }
+}
+
+#[cfg(feature = "ktx2")]
+fn ktx2_format(format: ktx2::Format) -> Option<TextureFormat> {
+    use ktx2::Format as F;
+    use TextureFormat as T;
+
+    let format = match format {
+        F::R8_UNORM => T::R8Unorm,
+        F::R8_SNORM => T::R8Snorm,
+        F::R8_UINT => T::R8Uint,
+        F::R8_SINT => T::R8Sint,
+        F::R8G8_UNORM => T::Rg8Unorm,
+        F::R8G8_SNORM => T::Rg8Snorm,
+        F::R8G8_UINT => T::Rg8Uint,
+        F::R8G8_SINT => T::Rg8Sint,
+        F::R8G8B8A8_UNORM => T::Rgba8Unorm,
+        F::R8G8B8A8_SRGB => T::Rgba8UnormSrgb,
+        F::R8G8B8A8_SNORM => T::Rgba8Snorm,
+        F::R8G8B8A8_UINT => T::Rgba8Uint,
+        F::R8G8B8A8_SINT => T::Rgba8Sint,
+        F::B8G8R8A8_UNORM => T::Bgra8Unorm,
+        F::B8G8R8A8_SRGB => T::Bgra8UnormSrgb,
+        F::A2B10G10R10_UNORM_PACK32 => T::Rgb10a2Unorm,
+        F::A2B10G10R10_UINT_PACK32 => T::Rgb10a2Uint,
+        F::R16_UNORM => T::R16Unorm,
+        F::R16_SNORM => T::R16Snorm,
+        F::R16_UINT => T::R16Uint,
+        F::R16_SINT => T::R16Sint,
+        F::R16_SFLOAT => T::R16Float,
+        F::R16G16_UNORM => T::Rg16Unorm,
+        F::R16G16_SNORM => T::Rg16Snorm,
+        F::R16G16_UINT => T::Rg16Uint,
+        F::R16G16_SINT => T::Rg16Sint,
+        F::R16G16_SFLOAT => T::Rg16Float,
+        F::R16G16B16A16_UNORM => T::Rgba16Unorm,
+        F::R16G16B16A16_SNORM => T::Rgba16Snorm,
+        F::R16G16B16A16_UINT => T::Rgba16Uint,
+        F::R16G16B16A16_SINT => T::Rgba16Sint,
+        F::R16G16B16A16_SFLOAT => T::Rgba16Float,
+        F::R32_UINT => T::R32Uint,
+        F::R32_SINT => T::R32Sint,
+        F::R32_SFLOAT => T::R32Float,
+        F::R32G32_UINT => T::Rg32Uint,
+        F::R32G32_SINT => T::Rg32Sint,
+        F::R32G32_SFLOAT => T::Rg32Float,
+        F::R32G32B32A32_UINT => T::Rgba32Uint,
+        F::R32G32B32A32_SINT => T::Rgba32Sint,
+        F::R32G32B32A32_SFLOAT => T::Rgba32Float,
+        F::B10G11R11_UFLOAT_PACK32 => T::Rg11b10Float,
+        F::E5B9G9R9_UFLOAT_PACK32 => T::Rgb9e5Ufloat,
+        F::D16_UNORM => T::Depth16Unorm,
+        F::D32_SFLOAT => T::Depth32Float,
+        F::BC1_RGB_UNORM_BLOCK | F::BC1_RGBA_UNORM_BLOCK => T::Bc1RgbaUnorm,
+        F::BC1_RGB_SRGB_BLOCK | F::BC1_RGBA_SRGB_BLOCK => T::Bc1RgbaUnormSrgb,
+        F::BC2_UNORM_BLOCK => T::Bc2RgbaUnorm,
+        F::BC2_SRGB_BLOCK => T::Bc2RgbaUnormSrgb,
+        F::BC3_UNORM_BLOCK => T::Bc3RgbaUnorm,
+        F::BC3_SRGB_BLOCK => T::Bc3RgbaUnormSrgb,
+        F::BC4_UNORM_BLOCK => T::Bc4RUnorm,
+        F::BC4_SNORM_BLOCK => T::Bc4RSnorm,
+        F::BC5_UNORM_BLOCK => T::Bc5RgUnorm,
+        F::BC5_SNORM_BLOCK => T::Bc5RgSnorm,
+        F::BC6H_UFLOAT_BLOCK => T::Bc6hRgbUfloat,
+        F::BC6H_SFLOAT_BLOCK => T::Bc6hRgbFloat,
+        F::BC7_UNORM_BLOCK => T::Bc7RgbaUnorm,
+        F::BC7_SRGB_BLOCK => T::Bc7RgbaUnormSrgb,
+        F::ETC2_R8G8B8_UNORM_BLOCK => T::Etc2Rgb8Unorm,
+        F::ETC2_R8G8B8_SRGB_BLOCK => T::Etc2Rgb8UnormSrgb,
+        F::ETC2_R8G8B8A1_UNORM_BLOCK => T::Etc2Rgb8A1Unorm,
+        F::ETC2_R8G8B8A1_SRGB_BLOCK => T::Etc2Rgb8A1UnormSrgb,
+        F::ETC2_R8G8B8A8_UNORM_BLOCK => T::Etc2Rgba8Unorm,
+        F::ETC2_R8G8B8A8_SRGB_BLOCK => T::Etc2Rgba8UnormSrgb,
+        F::EAC_R11_UNORM_BLOCK => T::EacR11Unorm,
+        F::EAC_R11_SNORM_BLOCK => T::EacR11Snorm,
+        F::EAC_R11G11_UNORM_BLOCK => T::EacRg11Unorm,
+        F::EAC_R11G11_SNORM_BLOCK => T::EacRg11Snorm,
+        _ => return ktx2_astc_format(format)
+    };
+
+    Some(format)
+}
+
+#[cfg(feature = "ktx2")]
+fn ktx2_astc_format(format: ktx2::Format) -> Option<TextureFormat> {
+    use ktx2::Format as F;
+
+    const BLOCKS: [(ktx2::Format, ktx2::Format, ktx2::Format, AstcBlock); 14] = [
+        (F::ASTC_4x4_UNORM_BLOCK, F::ASTC_4x4_SRGB_BLOCK, F::ASTC_4x4_SFLOAT_BLOCK, AstcBlock::B4x4),
+        (F::ASTC_5x4_UNORM_BLOCK, F::ASTC_5x4_SRGB_BLOCK, F::ASTC_5x4_SFLOAT_BLOCK, AstcBlock::B5x4),
+        (F::ASTC_5x5_UNORM_BLOCK, F::ASTC_5x5_SRGB_BLOCK, F::ASTC_5x5_SFLOAT_BLOCK, AstcBlock::B5x5),
+        (F::ASTC_6x5_UNORM_BLOCK, F::ASTC_6x5_SRGB_BLOCK, F::ASTC_6x5_SFLOAT_BLOCK, AstcBlock::B6x5),
+        (F::ASTC_6x6_UNORM_BLOCK, F::ASTC_6x6_SRGB_BLOCK, F::ASTC_6x6_SFLOAT_BLOCK, AstcBlock::B6x6),
+        (F::ASTC_8x5_UNORM_BLOCK, F::ASTC_8x5_SRGB_BLOCK, F::ASTC_8x5_SFLOAT_BLOCK, AstcBlock::B8x5),
+        (F::ASTC_8x6_UNORM_BLOCK, F::ASTC_8x6_SRGB_BLOCK, F::ASTC_8x6_SFLOAT_BLOCK, AstcBlock::B8x6),
+        (F::ASTC_8x8_UNORM_BLOCK, F::ASTC_8x8_SRGB_BLOCK, F::ASTC_8x8_SFLOAT_BLOCK, AstcBlock::B8x8),
+        (F::ASTC_10x5_UNORM_BLOCK, F::ASTC_10x5_SRGB_BLOCK, F::ASTC_10x5_SFLOAT_BLOCK, AstcBlock::B10x5),
+        (F::ASTC_10x6_UNORM_BLOCK, F::ASTC_10x6_SRGB_BLOCK, F::ASTC_10x6_SFLOAT_BLOCK, AstcBlock::B10x6),
+        (F::ASTC_10x8_UNORM_BLOCK, F::ASTC_10x8_SRGB_BLOCK, F::ASTC_10x8_SFLOAT_BLOCK, AstcBlock::B10x8),
+        (F::ASTC_10x10_UNORM_BLOCK, F::ASTC_10x10_SRGB_BLOCK, F::ASTC_10x10_SFLOAT_BLOCK, AstcBlock::B10x10),
+        (F::ASTC_12x10_UNORM_BLOCK, F::ASTC_12x10_SRGB_BLOCK, F::ASTC_12x10_SFLOAT_BLOCK, AstcBlock::B12x10),
+        (F::ASTC_12x12_UNORM_BLOCK, F::ASTC_12x12_SRGB_BLOCK, F::ASTC_12x12_SFLOAT_BLOCK, AstcBlock::B12x12)
+    ];
+
+    BLOCKS.iter().find_map(|&(unorm, srgb, sfloat, block)| {
+        let channel = match format {
+            _ if format == unorm => AstcChannel::Unorm,
+            _ if format == srgb => AstcChannel::UnormSrgb,
+            _ if format == sfloat => AstcChannel::Hdr,
+            _ => return None
+        };
+
+        Some(TextureFormat::Astc { block, channel })
+    })
+}
+
+/// Reads a KTX2 file, decompressing Zstandard supercompressed levels. Levels are stored
+/// mip by mip, each holding every layer and cube face.
+#[cfg(feature = "ktx2")]
+pub(crate) fn read_ktx2(label: &'static str, bytes: &[u8]) -> Result<TextureFile, Error> {
+    let reader = ktx2::Reader::new(bytes).map_err(|error| texture_error(label, format!("invalid KTX2 file: {}", error)))?;
+    let header = reader.header();
+
+    let Some(format) = header.format else {
+        return Err(texture_error(label, "KTX2 files without a format (Basis Universal) must be transcoded first".into()));
+    };
+
+    let format = ktx2_format(format)
+        .ok_or_else(|| texture_error(label, format!("KTX2 format {:?} has no wgpu equivalent", format)))?;
+
+    let (dimension, depth) = match (header.pixel_height, header.pixel_depth) {
+        (0, _) => return Err(texture_error(label, "1D KTX2 textures are not supported".into())),
+        (_, 0) => (wgpu::TextureDimension::D2, 1),
+        (_, depth) => (wgpu::TextureDimension::D3, depth)
+    };
+
+    let layers = header.layer_count.max(1) * header.face_count;
+    let mut data = Vec::new();
+
+    for level in reader.levels() {
+        match header.supercompression_scheme {
+            None => data.extend_from_slice(level.data),
+            Some(ktx2::SupercompressionScheme::Zstandard) => {
+                use std::io::Read;
+
+                let mut decoder = ruzstd::StreamingDecoder::new(level.data)
+                    .map_err(|error| texture_error(label, format!("invalid Zstandard data: {}", error)))?;
+
+                decoder
+                    .read_to_end(&mut data)
+                    .map_err(|error| texture_error(label, format!("invalid Zstandard data: {}", error)))?;
+            },
+            Some(scheme) => {
+                return Err(texture_error(label, format!("KTX2 supercompression {:?} is not supported", scheme)));
+            }
+        }
+    }
+
+    Ok(TextureFile {
+        format,
+        dimension,
+        size: wgpu::Extent3d {
+            width: header.pixel_width,
+            height: header.pixel_height,
+            depth_or_array_layers: if depth > 1 { depth } else { layers }
+        },
+        mip_level_count: header.level_count.max(1),
+        cube: header.face_count == 6,
+        order: TextureDataOrder::MipMajor,
+        data
+    })
+}
+
+#[cfg(feature = "dds")]
+fn dxgi_format(format: ddsfile::DxgiFormat) -> Option<TextureFormat> {
+    use ddsfile::DxgiFormat as F;
+    use TextureFormat as T;
+
+    let format = match format {
+        F::R32G32B32A32_Float => T::Rgba32Float,
+        F::R32G32B32A32_UInt => T::Rgba32Uint,
+        F::R32G32B32A32_SInt => T::Rgba32Sint,
+        F::R16G16B16A16_Float => T::Rgba16Float,
+        F::R16G16B16A16_UNorm => T::Rgba16Unorm,
+        F::R16G16B16A16_UInt => T::Rgba16Uint,
+        F::R16G16B16A16_SNorm => T::Rgba16Snorm,
+        F::R16G16B16A16_SInt => T::Rgba16Sint,
+        F::R32G32_Float => T::Rg32Float,
+        F::R32G32_UInt => T::Rg32Uint,
+        F::R32G32_SInt => T::Rg32Sint,
+        F::R10G10B10A2_UNorm => T::Rgb10a2Unorm,
+        F::R10G10B10A2_UInt => T::Rgb10a2Uint,
+        F::R11G11B10_Float => T::Rg11b10Float,
+        F::R8G8B8A8_UNorm => T::Rgba8Unorm,
+        F::R8G8B8A8_UNorm_sRGB => T::Rgba8UnormSrgb,
+        F::R8G8B8A8_UInt => T::Rgba8Uint,
+        F::R8G8B8A8_SNorm => T::Rgba8Snorm,
+        F::R8G8B8A8_SInt => T::Rgba8Sint,
+        F::R16G16_Float => T::Rg16Float,
+        F::R16G16_UNorm => T::Rg16Unorm,
+        F::R16G16_UInt => T::Rg16Uint,
+        F::R16G16_SNorm => T::Rg16Snorm,
+        F::R16G16_SInt => T::Rg16Sint,
+        F::D32_Float => T::Depth32Float,
+        F::R32_Float => T::R32Float,
+        F::R32_UInt => T::R32Uint,
+        F::R32_SInt => T::R32Sint,
+        F::R8G8_UNorm => T::Rg8Unorm,
+        F::R8G8_UInt => T::Rg8Uint,
+        F::R8G8_SNorm => T::Rg8Snorm,
+        F::R8G8_SInt => T::Rg8Sint,
+        F::R16_Float => T::R16Float,
+        F::D16_UNorm => T::Depth16Unorm,
+        F::R16_UNorm => T::R16Unorm,
+        F::R16_UInt => T::R16Uint,
+        F::R16_SNorm => T::R16Snorm,
+        F::R16_SInt => T::R16Sint,
+        F::R8_UNorm => T::R8Unorm,
+        F::R8_UInt => T::R8Uint,
+        F::R8_SNorm => T::R8Snorm,
+        F::R8_SInt => T::R8Sint,
+        F::R9G9B9E5_SharedExp => T::Rgb9e5Ufloat,
+        F::BC1_UNorm => T::Bc1RgbaUnorm,
+        F::BC1_UNorm_sRGB => T::Bc1RgbaUnormSrgb,
+        F::BC2_UNorm => T::Bc2RgbaUnorm,
+        F::BC2_UNorm_sRGB => T::Bc2RgbaUnormSrgb,
+        F::BC3_UNorm => T::Bc3RgbaUnorm,
+        F::BC3_UNorm_sRGB => T::Bc3RgbaUnormSrgb,
+        F::BC4_UNorm => T::Bc4RUnorm,
+        F::BC4_SNorm => T::Bc4RSnorm,
+        F::BC5_UNorm => T::Bc5RgUnorm,
+        F::BC5_SNorm => T::Bc5RgSnorm,
+        F::B8G8R8A8_UNorm => T::Bgra8Unorm,
+        F::B8G8R8A8_UNorm_sRGB => T::Bgra8UnormSrgb,
+        F::BC6H_UF16 => T::Bc6hRgbUfloat,
+        F::BC6H_SF16 => T::Bc6hRgbFloat,
+        F::BC7_UNorm => T::Bc7RgbaUnorm,
+        F::BC7_UNorm_sRGB => T::Bc7RgbaUnormSrgb,
+        _ => return None
+    };
+
+    Some(format)
+}
+
+#[cfg(feature = "dds")]
+fn d3d_format(format: ddsfile::D3DFormat) -> Option<TextureFormat> {
+    use ddsfile::D3DFormat as F;
+    use TextureFormat as T;
+
+    let format = match format {
+        F::A8B8G8R8 => T::Rgba8Unorm,
+        F::A8R8G8B8 => T::Bgra8Unorm,
+        F::G16R16 => T::Rg16Unorm,
+        F::A2B10G10R10 => T::Rgb10a2Unorm,
+        F::L8 => T::R8Unorm,
+        F::L16 => T::R16Unorm,
+        F::DXT1 => T::Bc1RgbaUnorm,
+        F::DXT3 => T::Bc2RgbaUnorm,
+        F::DXT5 => T::Bc3RgbaUnorm,
+        F::R16F => T::R16Float,
+        F::G16R16F => T::Rg16Float,
+        F::A16B16G16R16F => T::Rgba16Float,
+        F::R32F => T::R32Float,
+        F::G32R32F => T::Rg32Float,
+        F::A32B32G32R32F => T::Rgba32Float,
+        F::A16B16G16R16 => T::Rgba16Unorm,
+        _ => return None
+    };
+
+    Some(format)
+}
+
+/// Reads a DDS file, with or without the DX10 header. Layers are stored one after
+/// another, each holding its whole mip chain.
+#[cfg(feature = "dds")]
+pub(crate) fn read_dds(label: &'static str, bytes: &[u8]) -> Result<TextureFile, Error> {
+    let dds = ddsfile::Dds::read(bytes).map_err(|error| texture_error(label, format!("invalid DDS file: {}", error)))?;
+
+    let format = match (dds.get_dxgi_format(), dds.get_d3d_format()) {
+        (Some(format), _) => dxgi_format(format).ok_or_else(|| texture_error(label, format!("DXGI format {:?} has no wgpu equivalent", format)))?,
+        (None, Some(format)) => d3d_format(format).ok_or_else(|| texture_error(label, format!("D3D format {:?} has no wgpu equivalent", format)))?,
+        (None, None) => return Err(texture_error(label, "the DDS pixel format is not recognized".into()))
+    };
+
+    let volume = match &dds.header10 {
+        Some(header10) => header10.resource_dimension == ddsfile::D3D10ResourceDimension::Texture3D,
+        None => dds.header.caps2.contains(ddsfile::Caps2::VOLUME)
+    };
+
+    let cube = match &dds.header10 {
+        Some(header10) => header10.misc_flag.contains(ddsfile::MiscFlag::TEXTURECUBE),
+        None => dds.header.caps2.contains(ddsfile::Caps2::CUBEMAP)
+    };
+
+    // The DX10 header counts whole cubes, the legacy header reports six layers already
+    let layers = match &dds.header10 {
+        Some(_) if cube => dds.get_num_array_layers() * 6,
+        _ => dds.get_num_array_layers()
+    };
+
+    let (dimension, depth_or_array_layers) = match volume {
+        true => (wgpu::TextureDimension::D3, dds.get_depth().max(1)),
+        false => (wgpu::TextureDimension::D2, layers.max(1))
+    };
+
+    Ok(TextureFile {
+        format,
+        dimension,
+        size: wgpu::Extent3d { width: dds.get_width(), height: dds.get_height(), depth_or_array_layers },
+        mip_level_count: dds.get_num_mipmap_levels().max(1),
+        cube,
+        order: TextureDataOrder::LayerMajor,
+        data: dds.data
+    })
+}
@@ -0,0 +1,65 @@
+/// The number of invocations to run in each dimension, converted from `u32`,
+/// `(u32, u32)`, `(u32, u32, u32)` or `[u32; 3]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Invocations(pub [u32; 3]);
+
+impl From<u32> for Invocations {
+    fn from(x: u32) -> Self {
+        Self([x, 1, 1])
+    }
+}
+
+impl From<(u32, u32)> for Invocations {
+    fn from((x, y): (u32, u32)) -> Self {
+        Self([x, y, 1])
+    }
+}
+
+impl From<(u32, u32, u32)> for Invocations {
+    fn from((x, y, z): (u32, u32, u32)) -> Self {
+        Self([x, y, z])
+    }
+}
+
+impl From<[u32; 3]> for Invocations {
+    fn from(invocations: [u32; 3]) -> Self {
+        Self(invocations)
+    }
+}
+
+/// One kernel launch of `ComputeProgram::run_blocking`.
+pub struct Dispatch<'a> {
+    pub kernel: &'static str,
+    pub bind_groups: &'a [&'static str],
+    pub invocations: Invocations
+}
+
+/// Computes the workgroup counts covering `invocations`.
+///
+/// A one-dimensional dispatch with more workgroups than `max_per_dimension` is folded
+/// into the y and then z dimensions. Kernels dispatched this way must rebuild their
+/// linear index from `workgroup_id` and `num_workgroups`, and skip indices past the end.
+pub(crate) fn workgroup_counts(invocations: Invocations, workgroup_size: [u32; 3], max_per_dimension: u32) -> Result<[u32; 3], String> {
+    let [x, y, z] = [0, 1, 2].map(|i| invocations.0[i].div_ceil(workgroup_size[i]));
+
+    if x <= max_per_dimension && y <= max_per_dimension && z <= max_per_dimension {
+        return Ok([x, y, z]);
+    }
+
+    if y != 1 || z != 1 {
+        return Err(format!(
+            "{:?} workgroups exceed the limit of {} per dimension, and only one-dimensional dispatches can be split",
+            [x, y, z], max_per_dimension
+        ));
+    }
+
+    let folded_y = x.div_ceil(max_per_dimension);
+    let folded_z = folded_y.div_ceil(max_per_dimension);
+
+    if folded_z > max_per_dimension {
+        return Err(format!("{} workgroups exceed the limit of {} per dimension even when split", x, max_per_dimension));
+    }
+
+    let y = folded_y.div_ceil(folded_z);
+    Ok([x.div_ceil(y * folded_z), y, folded_z])
+}
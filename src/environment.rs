@@ -0,0 +1,242 @@
+use crate::{labels, BindGroupItem, ComputeKernel, ComputeProgram, Error, Preprocessor};
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// Sizes and sample counts for `ComputeProgram::add_environment_maps`.
+#[derive(Clone, Copy, Debug)]
+pub struct EnvironmentMapOptions {
+    /// The face size of the environment cube. It gets a full mip chain.
+    pub size: u32,
+    /// The face size of mip 0 of the prefiltered specular cube
+    pub specular_size: u32,
+    /// The number of specular mips, from roughness 0 at mip 0 to roughness 1 at the last
+    pub specular_mip_levels: u32,
+    /// GGX samples per texel of the specular cube
+    pub specular_samples: u32,
+    /// The face size of the irradiance cube
+    pub irradiance_size: u32
+}
+
+impl Default for EnvironmentMapOptions {
+    fn default() -> Self {
+        Self {
+            size: 512,
+            specular_size: 256,
+            specular_mip_levels: 6,
+            specular_samples: 512,
+            irradiance_size: 32
+        }
+    }
+}
+
+/// The labels of the cube textures created by `ComputeProgram::add_environment_maps`.
+/// All of them are `Rgba16Float` with a `Cube` view.
+#[derive(Clone, Copy, Debug)]
+pub struct EnvironmentMaps {
+    /// `"{label}.environment"`, the source converted to a cube with a full mip chain
+    pub environment: &'static str,
+    /// `"{label}.specular"`, prefiltered for roughness `mip / (mip_level_count - 1)`
+    pub specular: &'static str,
+    /// `"{label}.irradiance"`, the cosine-weighted diffuse irradiance
+    pub irradiance: &'static str
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct Params {
+    roughness: f32,
+    samples: u32,
+    source_size: f32,
+    mip: f32
+}
+
+fn create_cube(device: &wgpu::Device, size: u32, mip_level_count: u32) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size: wgpu::Extent3d { width: size, height: size, depth_or_array_layers: 6 },
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[]
+    })
+}
+
+/// Stores a view of `mips` of a cube texture under `label`, as a cube or as a 2D array of
+/// its six faces for storage writes.
+fn add_view<P: ComputeProgram + ?Sized>(
+    program: &mut P,
+    label: &'static str,
+    texture: &'static str,
+    mips: std::ops::Range<u32>,
+    dimension: wgpu::TextureViewDimension
+) -> &'static str {
+    let view = program.storage().textures[texture].create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(dimension),
+        base_mip_level: mips.start,
+        mip_level_count: Some(mips.end - mips.start),
+        ..Default::default()
+    });
+
+    program.storage_mut().insert_texture_view(label, view, dimension);
+    label
+}
+
+/// The destination of a kernel: one mip of `texture`, and the parameters of the pass.
+fn destination<P: ComputeProgram + ?Sized>(
+    program: &mut P,
+    label: &'static str,
+    texture: &'static str,
+    mip: u32,
+    params: Params
+) -> [BindGroupItem; 2] {
+    let view = add_view(program, labels::derived(texture, &format!("mip{}", mip)), texture, mip..mip + 1, wgpu::TextureViewDimension::D2Array);
+    let params_label = labels::derived(label, "params");
+    program.add_buffer_init(params_label, wgpu::BufferUsages::UNIFORM, &[params]);
+
+    [
+        BindGroupItem::StorageTextureView { label: view, access: wgpu::StorageTextureAccess::WriteOnly, format: FORMAT },
+        BindGroupItem::UniformBuffer { label: params_label, min_binding_size: std::mem::size_of::<Params>() as u64 }
+    ]
+}
+
+/// Converts the equirectangular texture `equirect` to an environment cube, then derives
+/// the prefiltered specular and irradiance cubes from it, all in one submission.
+pub(crate) fn create<P: ComputeProgram + ?Sized>(
+    program: &mut P,
+    label: &'static str,
+    equirect: &'static str,
+    options: EnvironmentMapOptions
+) -> Result<EnvironmentMaps, Error> {
+    let device = program.compute().device.clone();
+    let source = &program.storage().textures[equirect];
+
+    let is_float = matches!(source.format().sample_type(None, None), Some(wgpu::TextureSampleType::Float { .. }));
+    if !is_float || source.dimension() != wgpu::TextureDimension::D2 || !source.usage().contains(wgpu::TextureUsages::TEXTURE_BINDING) {
+        return Err(Error::Texture {
+            label: equirect,
+            message: "environment maps need a 2D float texture created with `TEXTURE_BINDING`".into()
+        });
+    }
+
+    let equirect_view = source.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2),
+        base_array_layer: 0,
+        array_layer_count: Some(1),
+        ..Default::default()
+    });
+
+    let size = options.size.max(1);
+    let environment_mips = size.ilog2() + 1;
+    let specular_size = options.specular_size.max(1);
+    let specular_mips = options.specular_mip_levels.clamp(1, specular_size.ilog2() + 1);
+    let irradiance_size = options.irradiance_size.max(1);
+
+    let maps = EnvironmentMaps {
+        environment: labels::derived(label, "environment"),
+        specular: labels::derived(label, "specular"),
+        irradiance: labels::derived(label, "irradiance")
+    };
+
+    let storage = program.storage_mut();
+    storage.insert_texture(maps.environment, create_cube(&device, size, environment_mips), true);
+    storage.insert_texture(maps.specular, create_cube(&device, specular_size, specular_mips), true);
+    storage.insert_texture(maps.irradiance, create_cube(&device, irradiance_size, 1), true);
+
+    let module = labels::derived(label, "kernels");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.add_virtual_file("environment.wgsl", include_str!("shaders/environment.wgsl"));
+    crate::primitives::add_module(program, module, &preprocessor, "environment.wgsl")?;
+
+    let sampler = labels::derived(label, "sampler");
+    program.add_sampler(sampler, wgpu::SamplerDescriptor {
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
+
+    // A sampled cube is bound as group 1 by every kernel but the first
+    let sampled = |view| [
+        BindGroupItem::TextureView { label: view, sample_type: wgpu::TextureSampleType::Float { filterable: true } },
+        BindGroupItem::Sampler { label: sampler }
+    ];
+
+    let kernel = |entry_point| ComputeKernel { label: labels::derived(label, entry_point), entry_point, constants: &[] };
+    let mut passes: Vec<(&'static str, Vec<&'static str>, u32)> = Vec::new();
+
+    // The equirectangular source is read with `textureLoad`, so it may be unfilterable
+    let to_cube = labels::derived(label, "to_cube");
+    let source_view = labels::derived(label, "equirect");
+    program.storage_mut().insert_texture_view(source_view, equirect_view, wgpu::TextureViewDimension::D2);
+
+    let [target, params] = destination(program, to_cube, maps.environment, 0, Params::default());
+    program.add_bind_group(to_cube, &[
+        target,
+        params,
+        BindGroupItem::TextureView { label: source_view, sample_type: wgpu::TextureSampleType::Float { filterable: false } }
+    ]);
+    program.add_compute_pipelines(module, &[to_cube], &[kernel("equirect_to_cube")], &[], None)?;
+    passes.push((kernel("equirect_to_cube").label, vec![to_cube], size));
+
+    // Each mip samples a cube view of only the mip above it, which it is not writing to
+    for mip in 1..environment_mips {
+        let downsample = labels::derived(label, &format!("downsample{}", mip));
+        let previous = labels::derived(label, &format!("downsample{}.source", mip));
+        let previous_view = labels::derived(maps.environment, &format!("cube{}", mip - 1));
+
+        add_view(program, previous_view, maps.environment, mip - 1..mip, wgpu::TextureViewDimension::Cube);
+        let items = destination(program, downsample, maps.environment, mip, Params::default());
+        program.add_bind_group(downsample, &items);
+        program.add_bind_group(previous, &sampled(previous_view));
+
+        if mip == 1 {
+            program.add_compute_pipelines(module, &[downsample, previous], &[kernel("downsample")], &[], None)?;
+        }
+
+        passes.push((kernel("downsample").label, vec![downsample, previous], (size >> mip).max(1)));
+    }
+
+    let environment = labels::derived(label, "environment_cube");
+    program.add_bind_group(environment, &sampled(maps.environment));
+
+    for mip in 0..specular_mips {
+        let specular = labels::derived(label, &format!("prefilter{}", mip));
+        let params = Params {
+            roughness: mip as f32 / (specular_mips - 1).max(1) as f32,
+            samples: options.specular_samples.max(1),
+            source_size: size as f32,
+            mip: 0.0
+        };
+
+        let items = destination(program, specular, maps.specular, mip, params);
+        program.add_bind_group(specular, &items);
+        passes.push((kernel("prefilter_specular").label, vec![specular, environment], (specular_size >> mip).max(1)));
+    }
+
+    // Sample a mip with about 32 texels per face, the convolution is smooth anyway
+    let irradiance = labels::derived(label, "convolve");
+    let params = Params {
+        roughness: 1.0,
+        samples: 32,
+        source_size: size as f32,
+        mip: size.ilog2().saturating_sub(5) as f32
+    };
+
+    let items = destination(program, irradiance, maps.irradiance, 0, params);
+    program.add_bind_group(irradiance, &items);
+    passes.push((kernel("irradiance").label, vec![irradiance, environment], irradiance_size));
+
+    let specular = labels::derived(label, "prefilter0");
+    program.add_compute_pipelines(module, &[specular, environment], &[kernel("prefilter_specular"), kernel("irradiance")], &[], None)?;
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+
+    for (kernel, bind_groups, size) in &passes {
+        program.dispatch(&mut encoder, kernel, bind_groups, (*size, *size, 6))?;
+    }
+
+    program.compute().queue.submit(Some(encoder.finish()));
+    Ok(maps)
+}
@@ -0,0 +1,69 @@
+use std::path::PathBuf;
+
+/// Errors reported by the fallible `ComputeProgram` helpers.
+///
+/// Some variants only exist with a cargo feature, and features are unified across a
+/// build, so matches on `Error` need a wildcard arm.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// A file could not be read or written.
+    Io { path: PathBuf, source: std::io::Error },
+    /// The WGSL preprocessor rejected its input.
+    Preprocessor { file: String, line: u32, message: String },
+    /// A shader module failed to compile. `message` is ready to be printed.
+    Shader { label: &'static str, message: String },
+    /// A pipeline could not be created from its module.
+    Pipeline { label: &'static str, message: String },
+    /// A kernel could not be dispatched.
+    Dispatch { label: &'static str, message: String },
+    /// A buffer could not be mapped for reading.
+    Map { label: &'static str, source: wgpu::BufferAsyncError },
+    /// A buffer range was misaligned or out of bounds.
+    Range { label: &'static str, message: String },
+    /// A buffer was read as a different type than it was created for.
+    ElementType { label: &'static str, expected: &'static str, found: &'static str },
+    /// A texture could not be copied, read or written.
+    Texture { label: &'static str, message: String },
+    /// A NumPy `.npy` array was malformed or did not match the buffer's element type.
+    Npy { label: &'static str, message: String },
+    /// An array did not have the shape or number of dimensions needed.
+    #[cfg(feature = "ndarray")]
+    Shape { label: &'static str, message: String },
+    /// An image could not be decoded or encoded.
+    #[cfg(feature = "image")]
+    Image { label: &'static str, source: image::ImageError }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Preprocessor { file, line, message } => write!(f, "{}:{}: {}", file, line, message),
+            Error::Shader { label, message } => write!(f, "failed to compile shader module `{}`:\n{}", label, message),
+            Error::Pipeline { label, message } => write!(f, "failed to create pipeline `{}`: {}", label, message),
+            Error::Dispatch { label, message } => write!(f, "failed to dispatch `{}`: {}", label, message),
+            Error::Map { label, source } => write!(f, "failed to map buffer `{}`: {}", label, source),
+            Error::Range { label, message } => write!(f, "invalid range of buffer `{}`: {}", label, message),
+            Error::ElementType { label, expected, found } => write!(f, "buffer `{}` holds `{}`, not `{}`", label, expected, found),
+            Error::Texture { label, message } => write!(f, "invalid access to texture `{}`: {}", label, message),
+            Error::Npy { label, message } => write!(f, "invalid .npy array for buffer `{}`: {}", label, message),
+            #[cfg(feature = "ndarray")]
+            Error::Shape { label, message } => write!(f, "invalid array shape for `{}`: {}", label, message),
+            #[cfg(feature = "image")]
+            Error::Image { label, source } => write!(f, "failed to convert texture `{}`: {}", label, source)
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Map { source, .. } => Some(source),
+            #[cfg(feature = "image")]
+            Error::Image { source, .. } => Some(source),
+            _ => None
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::path::Path;
+
+use image::DynamicImage;
+use wgpu::TextureFormat;
+
+use crate::{ComputeProgram, Error, ImageSource};
+
+/// The texture formats images can be converted to and from.
+pub(crate) const IMAGE_FORMATS: &[TextureFormat] = &[
+    TextureFormat::Rgba8Unorm,
+    TextureFormat::Rgba8UnormSrgb,
+    TextureFormat::Bgra8Unorm,
+    TextureFormat::Bgra8UnormSrgb,
+    TextureFormat::R8Unorm,
+    TextureFormat::R32Float,
+    TextureFormat::Rg32Float,
+    TextureFormat::Rgba16Float,
+    TextureFormat::Rgba32Float
+];
+
+fn map_image_error(label: &'static str, path: Option<&Path>, error: image::ImageError) -> Error {
+    match (path, error) {
+        (Some(path), image::ImageError::IoError(source)) => Error::Io { path: path.to_owned(), source },
+        (_, source) => Error::Image { label, source }
+    }
+}
+
+fn unsupported(label: &'static str, format: TextureFormat) -> Error {
+    Error::Texture {
+        label,
+        message: format!("cannot convert images to or from {:?}, supported formats are {:?}", format, IMAGE_FORMATS)
+    }
+}
+
+pub(crate) fn load(label: &'static str, source: ImageSource) -> Result<DynamicImage, Error> {
+    image::load_from_memory(&source.read()?).map_err(|error| map_image_error(label, None, error))
+}
+
+/// Converts an image to tightly packed texels of `format`. Channels are copied as they
+/// are: the format decides whether the GPU treats them as sRGB or linear. Formats with
+/// fewer channels keep the first ones, so `R8Unorm` and `R32Float` take the red channel
+/// of colour images rather than their luminance.
+pub(crate) fn to_texels(label: &'static str, image: DynamicImage, format: TextureFormat) -> Result<Vec<u8>, Error> {
+    let texels = match format {
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => image.into_rgba8().into_raw(),
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => {
+            let mut texels = image.into_rgba8().into_raw();
+            texels.chunks_exact_mut(4).for_each(|texel| texel.swap(0, 2));
+            texels
+        },
+        TextureFormat::R8Unorm => image.into_rgba8().into_raw().chunks_exact(4).map(|texel| texel[0]).collect(),
+        TextureFormat::R32Float => {
+            let texels: Vec<_> = image.into_rgba32f().into_raw().chunks_exact(4).map(|texel| texel[0]).collect();
+            bytemuck::cast_slice(&texels).to_vec()
+        },
+        TextureFormat::Rg32Float => {
+            let texels: Vec<_> = image.into_rgba32f().into_raw().chunks_exact(4).flat_map(|texel| [texel[0], texel[1]]).collect();
+            bytemuck::cast_slice(&texels).to_vec()
+        },
+        TextureFormat::Rgba16Float => {
+            let texels: Vec<_> = image.into_rgba32f().into_raw().into_iter().map(half::f16::from_f32).collect();
+            bytemuck::cast_slice(&texels).to_vec()
+        },
+        TextureFormat::Rgba32Float => bytemuck::cast_slice(&image.into_rgba32f().into_raw()).to_vec(),
+        _ => return Err(unsupported(label, format))
+    };
+
+    Ok(texels)
+}
+
+/// Converts tightly packed texels of `format` to an image. `R32Float` becomes a grey
+/// RGBA image and `Rg32Float` an RGBA image with blue set to zero.
+pub(crate) fn from_texels(label: &'static str, format: TextureFormat, width: u32, height: u32, texels: Vec<u8>) -> Result<DynamicImage, Error> {
+    let rgba32f = |texels: Vec<f32>| DynamicImage::ImageRgba32F(image::Rgba32FImage::from_raw(width, height, texels).unwrap());
+
+    let image = match format {
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => {
+            DynamicImage::ImageRgba8(image::RgbaImage::from_raw(width, height, texels).unwrap())
+        },
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => {
+            let mut texels = texels;
+            texels.chunks_exact_mut(4).for_each(|texel| texel.swap(0, 2));
+            DynamicImage::ImageRgba8(image::RgbaImage::from_raw(width, height, texels).unwrap())
+        },
+        TextureFormat::R8Unorm => DynamicImage::ImageLuma8(image::GrayImage::from_raw(width, height, texels).unwrap()),
+        TextureFormat::R32Float => {
+            let values: Vec<f32> = bytemuck::pod_collect_to_vec(&texels);
+            rgba32f(values.into_iter().flat_map(|v| [v, v, v, 1.0]).collect())
+        },
+        TextureFormat::Rg32Float => {
+            let values: Vec<f32> = bytemuck::pod_collect_to_vec(&texels);
+            rgba32f(values.chunks_exact(2).flat_map(|v| [v[0], v[1], 0.0, 1.0]).collect())
+        },
+        TextureFormat::Rgba16Float => {
+            let values: Vec<half::f16> = bytemuck::pod_collect_to_vec(&texels);
+            rgba32f(values.into_iter().map(half::f16::to_f32).collect())
+        },
+        TextureFormat::Rgba32Float => rgba32f(bytemuck::pod_collect_to_vec(&texels)),
+        _ => return Err(unsupported(label, format))
+    };
+
+    Ok(image)
+}
+
+/// Reads mip 0 of a texture back and saves it as `file_format`, creating its staging
+/// buffer if needed.
+///
+/// PNG stores 8 bits per channel, so float textures are clamped to `0..=1` without any
+/// transfer function applied. OpenEXR keeps 32-bit float RGBA and Radiance HDR float RGB.
+pub(crate) fn save_texture<P: ComputeProgram + ?Sized>(
+    program: &mut P,
+    label: &'static str,
+    path: &Path,
+    file_format: image::ImageFormat
+) -> Result<(), Error> {
+    if !program.storage().texture_staging_buffers.contains_key(label) {
+        program.add_texture_staging(label)?;
+    }
+
+    let texture = &program.storage().textures[label];
+    let (format, width, height) = (texture.format(), texture.width(), texture.height());
+
+    let texels = program.read_texture::<u8>(label, 0, 0)?;
+    let image = from_texels(label, format, width, height, texels)?;
+
+    let image = match (file_format, image) {
+        (image::ImageFormat::Png, image @ DynamicImage::ImageRgba32F(_)) => DynamicImage::ImageRgba8(image.to_rgba8()),
+        (image::ImageFormat::OpenExr, image) => DynamicImage::ImageRgba32F(image.into_rgba32f()),
+        (image::ImageFormat::Hdr, image) => DynamicImage::ImageRgb32F(image.into_rgb32f()),
+        (_, image) => image
+    };
+
+    image
+        .save_with_format(path, file_format)
+        .map_err(|error| map_image_error(label, Some(path), error))
+}
@@ -0,0 +1,19 @@
+use std::{collections::HashSet, sync::{Mutex, OnceLock}};
+
+/// Returns the `&'static str` label `"{label}.{suffix}"` for resources a helper creates
+/// on behalf of `label`. Each distinct label is leaked once and reused afterwards.
+pub(crate) fn derived(label: &str, suffix: &str) -> &'static str {
+    static LABELS: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+
+    let name = format!("{}.{}", label, suffix);
+    let mut labels = LABELS.get_or_init(Default::default).lock().unwrap();
+
+    match labels.get(name.as_str()) {
+        Some(&interned) => interned,
+        None => {
+            let interned: &'static str = Box::leak(name.into_boxed_str());
+            labels.insert(interned);
+            interned
+        }
+    }
+}
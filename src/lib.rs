@@ -1,6 +1,48 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{borrow::Cow, collections::HashMap, future::Future, ops::Range, path::Path, sync::{Arc, OnceLock}};
 use bytemuck::Pod;
-use wgpu::{BufferUsages, ShaderStages};
+use wgpu::{util::TextureDataOrder, BufferUsages, ShaderStages};
+
+#[cfg(feature = "ndarray")]
+mod arrays;
+mod buffer;
+#[cfg(any(feature = "ktx2", feature = "dds"))]
+mod containers;
+mod dispatch;
+mod environment;
+mod error;
+#[cfg(feature = "image")]
+mod images;
+mod labels;
+mod npy;
+mod pipeline;
+mod preprocessor;
+mod primitives;
+mod reload;
+mod shader;
+mod staging;
+mod texture;
+mod typed;
+mod upload;
+
+pub use naga;
+
+use shader::SourceText;
+
+pub use buffer::BufferElements;
+pub use dispatch::{Dispatch, Invocations};
+pub use environment::{EnvironmentMapOptions, EnvironmentMaps};
+pub use error::Error;
+pub use npy::NpyElement;
+pub use pipeline::{ComputePipelineParams, RenderPipelineParams, VertexBufferLayoutParams};
+pub use preprocessor::{PreprocessedSource, Preprocessor, SourceFile};
+pub use primitives::{BitonicSort, BitonicSortPasses, ElementType, RadixSortPasses, Reduction, ReductionPasses, Scan, ScanPasses};
+pub use reload::ModuleFiles;
+pub use staging::StagingRing;
+pub use texture::{ImageSource, TextureRegion};
+pub use typed::{GpuBuffer, GpuVec};
+pub use upload::UploadBelt;
+#[cfg(feature = "hot-reload")]
+pub use reload::ModuleWatcher;
 
 pub struct Compute {
     pub instance: Arc<wgpu::Instance>,
@@ -45,12 +87,26 @@ pub enum BindGroupItem {
     Texture { label: &'static str },
     TextureView { label: &'static str, sample_type: wgpu::TextureSampleType },
     StorageTexture { label: &'static str, access: wgpu::StorageTextureAccess },
+    /// A view in `Storage::texture_views` without a texture of the same label, e.g. one
+    /// mip of a larger texture
+    StorageTextureView { label: &'static str, access: wgpu::StorageTextureAccess, format: wgpu::TextureFormat },
     Sampler { label: &'static str }
 }
 
+/// A compute pipeline created from `entry_point`.
+///
+/// `constants` set the module's `override` declarations for this kernel only, on top of
+/// the constants in the `compilation_options` given to `add_compute_pipelines`. Several
+/// kernels may share an entry point with different constants to create specialized
+/// variants, e.g. one per tile size.
+///
+/// Overrides cannot be used in `@workgroup_size`, which naga needs as a constant when it
+/// parses the module. Kernels with different workgroup sizes need one module each, e.g.
+/// from the preprocessor with a different `#define`.
 pub struct ComputeKernel {
     pub label: &'static str,
-    pub entry_point: &'static str
+    pub entry_point: &'static str,
+    pub constants: &'static [(&'static str, f64)]
 }
 
 pub struct RenderKernel {
@@ -59,11 +115,21 @@ pub struct RenderKernel {
     pub fragment: &'static str
 }
 
+#[derive(Default)]
 pub struct Storage {
     pub modules: HashMap<&'static str, wgpu::ShaderModule>,
+    /// The IR of every module that was validated by `add_module` and friends
+    pub naga_modules: HashMap<&'static str, naga::Module>,
     pub buffers: HashMap<&'static str, wgpu::Buffer>,
+    /// The element type and count of buffers created for a specific type
+    pub buffer_elements: HashMap<&'static str, BufferElements>,
+    /// The shape of the elements of buffers created from `.npy` files or arrays, used to
+    /// restore it on readback
+    pub buffer_shapes: HashMap<&'static str, Vec<usize>>,
     pub textures: HashMap<&'static str, wgpu::Texture>,
     pub texture_views: HashMap<&'static str, wgpu::TextureView>,
+    /// The dimension of each view in `texture_views`, used for bind group layouts
+    pub texture_view_dimensions: HashMap<&'static str, wgpu::TextureViewDimension>,
     pub samplers: HashMap<&'static str, wgpu::Sampler>,
     pub bind_groups: HashMap<&'static str, wgpu::BindGroup>,
     pub bind_group_layouts: HashMap<&'static str, wgpu::BindGroupLayout>,
@@ -72,25 +138,67 @@ pub struct Storage {
     
     pub staging_buffers: HashMap<&'static str, wgpu::Buffer>,
     pub staging_senders: HashMap<&'static str, flume::Sender<Result<(), wgpu::BufferAsyncError>>>,
-    pub staging_receivers: HashMap<&'static str, flume::Receiver<Result<(), wgpu::BufferAsyncError>>>
+    pub staging_receivers: HashMap<&'static str, flume::Receiver<Result<(), wgpu::BufferAsyncError>>>,
+    pub staging_rings: HashMap<&'static str, StagingRing>,
+    /// The range of each staging buffer mapped by `prepare_staging_range` and not read yet
+    pub mapped_staging_ranges: HashMap<&'static str, Range<u64>>,
+    /// The background thread `read_buffer` hands its submissions to, started by the first read
+    pub read_poller: OnceLock<flume::Sender<wgpu::SubmissionIndex>>,
+    /// The passes of each reduction created by `add_reduction`
+    pub reductions: HashMap<&'static str, ReductionPasses>,
+    /// The levels of each prefix sum created by `add_scan`
+    pub scans: HashMap<&'static str, ScanPasses>,
+    /// The digit passes of each sort created by `add_radix_sort`
+    pub radix_sorts: HashMap<&'static str, RadixSortPasses>,
+    /// The steps of each sort created by `add_bitonic_sort`
+    pub bitonic_sorts: HashMap<&'static str, BitonicSortPasses>,
+    /// Staging buffers for texture readbacks, sized for the largest mip with padded rows
+    pub texture_staging_buffers: HashMap<&'static str, wgpu::Buffer>,
+    /// Used by `write_staged`. Replace it to change the chunk size.
+    pub uploads: UploadBelt,
+
+    /// Used by `add_module_preprocessed` to resolve `#include`s and predefined names
+    pub preprocessor: Preprocessor,
+
+    pub compute_pipeline_params: HashMap<&'static str, ComputePipelineParams>,
+    /// The `@workgroup_size` of each compute pipeline, used by `dispatch`
+    pub workgroup_sizes: HashMap<&'static str, [u32; 3]>,
+    pub render_pipeline_params: HashMap<&'static str, RenderPipelineParams>,
+    pub module_files: HashMap<&'static str, ModuleFiles>,
+    #[cfg(feature = "hot-reload")]
+    pub module_watcher: Option<ModuleWatcher>
 }
 
-impl Default for Storage {
-    fn default() -> Self {
-        Self { 
-            modules: Default::default(), 
-            buffers: Default::default(), 
-            textures: Default::default(), 
-            texture_views: Default::default(), 
-            samplers: Default::default(), 
-            bind_groups: Default::default(), 
-            bind_group_layouts: Default::default(), 
-            compute_pipelines: Default::default(), 
-            render_pipelines: Default::default(), 
-            staging_buffers: Default::default(), 
-            staging_senders: Default::default(),
-            staging_receivers: Default::default()
-        }
+impl Storage {
+    /// The dimension of a texture's view, `D2` for views inserted without one.
+    fn texture_view_dimension(&self, label: &'static str) -> wgpu::TextureViewDimension {
+        self.texture_view_dimensions.get(label).copied().unwrap_or(wgpu::TextureViewDimension::D2)
+    }
+
+    /// Stores a texture with a view of all its mips and layers. `cube` views the layers
+    /// as cube faces.
+    pub(crate) fn insert_texture(&mut self, label: &'static str, texture: wgpu::Texture, cube: bool) {
+        let dimension = texture::view_dimension(&texture, cube);
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(dimension),
+            ..Default::default()
+        });
+
+        self.texture_view_dimensions.insert(label, dimension);
+        self.texture_views.insert(label, view);
+        self.textures.insert(label, texture);
+    }
+
+    /// Stores a view of part of a texture under its own label, for `TextureView` and
+    /// `StorageTextureView` bind group items.
+    pub(crate) fn insert_texture_view(&mut self, label: &'static str, view: wgpu::TextureView, dimension: wgpu::TextureViewDimension) {
+        self.texture_view_dimensions.insert(label, dimension);
+        self.texture_views.insert(label, view);
+    }
+
+    fn insert_module(&mut self, label: &'static str, (module, naga_module): (wgpu::ShaderModule, naga::Module)) {
+        self.modules.insert(label, module);
+        self.naga_modules.insert(label, naga_module);
     }
 }
 
@@ -102,17 +210,170 @@ pub trait ComputeProgram {
     fn add_buffer(&mut self, label: &'static str, usage: wgpu::BufferUsages, size: u64) {
         let buffer = self.compute().device.create_buffer(&wgpu::BufferDescriptor {
             label: None,
-            size: size.into(),
+            size,
             usage,
             mapped_at_creation: false
         });
 
         self.storage_mut().buffers.insert(label, buffer);
+        self.storage_mut().buffer_elements.remove(label);
+        self.storage_mut().buffer_shapes.remove(label);
+    }
+
+    /// Creates a buffer holding `data`, written through a mapping at creation. An empty
+    /// `data` still creates a bindable buffer, recorded as holding no elements.
+    fn add_buffer_init<T: Pod>(&mut self, label: &'static str, usage: wgpu::BufferUsages, data: &[T]) {
+        let bytes: &[u8] = bytemuck::cast_slice(data);
+
+        let buffer = self.compute().device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: typed::buffer_size::<T>(data.len()),
+            usage,
+            mapped_at_creation: true
+        });
+
+        buffer.slice(..).get_mapped_range_mut()[..bytes.len()].copy_from_slice(bytes);
+        buffer.unmap();
+
+        self.storage_mut().buffers.insert(label, buffer);
+        self.storage_mut().buffer_elements.insert(label, BufferElements::of::<T>(data.len()));
+        self.storage_mut().buffer_shapes.remove(label);
+    }
+
+    /// Creates a buffer from a NumPy `.npy` file. The dtype must match `T`, e.g. `<f4` for
+    /// `f32`, and array elements like `[f32; 4]` need a trailing dimension of 4. The shape
+    /// is kept for `save_buffer_npy`.
+    fn add_buffer_from_npy<T: NpyElement>(&mut self, label: &'static str, path: impl AsRef<Path>, usage: wgpu::BufferUsages) -> Result<(), Error> {
+        npy::load::<Self, T>(self, label, path.as_ref(), usage)
+    }
+
+    /// Reads a buffer back through a staging buffer and saves it as a `.npy` file, with the
+    /// shape it was loaded with if the length still matches. The buffer needs `COPY_SRC`.
+    fn save_buffer_npy<T: NpyElement>(&self, label: &'static str, path: impl AsRef<Path>) -> Result<(), Error> {
+        npy::save::<Self, T>(self, label, path.as_ref())
+    }
+
+    /// Creates a buffer from the array `name` of a NumPy `.npz` archive, like
+    /// `add_buffer_from_npy`. Both stored and deflated archives are supported.
+    #[cfg(feature = "npz")]
+    fn add_buffer_from_npz<T: NpyElement>(&mut self, label: &'static str, path: impl AsRef<Path>, name: &str, usage: wgpu::BufferUsages) -> Result<(), Error> {
+        npy::load_npz::<Self, T>(self, label, path.as_ref(), name, usage)
+    }
+
+    /// Saves several buffers of the same element type as one `.npz` archive, with each
+    /// array named after its label.
+    #[cfg(feature = "npz")]
+    fn save_buffers_npz<T: NpyElement>(&self, path: impl AsRef<Path>, labels: &[&'static str]) -> Result<(), Error> {
+        npy::save_npz::<Self, T>(self, path.as_ref(), labels)
+    }
+
+    /// Creates a buffer holding the elements of `array` in row-major order, copying them
+    /// first if the view is not contiguous. The shape is restored by `read_buffer_array`.
+    #[cfg(feature = "ndarray")]
+    fn add_buffer_from_array<T: Pod, D: ndarray::Dimension>(&mut self, label: &'static str, usage: wgpu::BufferUsages, array: ndarray::ArrayView<T, D>) {
+        arrays::create_buffer(self, label, usage, array)
+    }
+
+    /// Reads a buffer back like `read_buffer`, shaped like the array or `.npy` file it
+    /// was created from. Other buffers, or ones whose length changed since, come back
+    /// flat, so `D` has to be `Ix1` or `IxDyn` for them.
+    #[cfg(feature = "ndarray")]
+    fn read_buffer_array<T: Pod, D: ndarray::Dimension>(&self, label: &'static str) -> impl Future<Output = Result<ndarray::Array<T, D>, Error>> + Send + 'static {
+        let shape = self.storage().buffer_shapes.get(label).cloned();
+        let read = self.read_buffer::<T>(label);
+
+        async move { arrays::to_array(label, shape, read.await?) }
+    }
+
+    /// Creates a zeroed buffer sized for `count` elements of `T`.
+    fn add_buffer_zeroed_for<T: Pod>(&mut self, label: &'static str, usage: wgpu::BufferUsages, count: usize) {
+        let size = (count * std::mem::size_of::<T>()) as u64;
+        self.add_buffer(label, usage, size.next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT));
+        self.storage_mut().buffer_elements.insert(label, BufferElements::of::<T>(count));
+    }
+
+    /// Creates a typed buffer holding `data`. `COPY_SRC` and `COPY_DST` are added to
+    /// `usage` so the buffer can be read and written through the handle.
+    fn add_gpu_buffer<T: Pod>(&mut self, label: &'static str, usage: wgpu::BufferUsages, data: &[T]) -> GpuBuffer<T> {
+        let bytes: &[u8] = bytemuck::cast_slice(data);
+
+        let buffer = self.compute().device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: typed::buffer_size::<T>(data.len()),
+            usage: usage | typed::TYPED_USAGES,
+            mapped_at_creation: true
+        });
+
+        buffer.slice(..).get_mapped_range_mut()[..bytes.len()].copy_from_slice(bytes);
+        buffer.unmap();
+
+        self.storage_mut().buffers.insert(label, buffer);
+        self.storage_mut().buffer_elements.insert(label, BufferElements::of::<T>(data.len()));
+        GpuBuffer::new(label, data.len())
+    }
+
+    /// Creates an empty growable buffer with room for `capacity` elements.
+    fn add_gpu_vec<T: Pod>(&mut self, label: &'static str, usage: wgpu::BufferUsages, capacity: usize) -> GpuVec<T> {
+        let usage = usage | typed::TYPED_USAGES;
+        self.add_buffer(label, usage, typed::buffer_size::<T>(capacity));
+        self.storage_mut().buffer_elements.insert(label, BufferElements::of::<T>(0));
+        GpuVec::new(label, usage, capacity)
     }
     
-    fn add_module(&mut self, label: &'static str, shader: wgpu::ShaderModuleDescriptor) {
-        let module = self.compute().device.create_shader_module(shader);
-        self.storage_mut().modules.insert(label, module);
+    /// Parses and validates WGSL and naga IR up front, returning an annotated snippet on
+    /// failure instead of letting wgpu panic. Other sources are passed to wgpu as is.
+    fn add_module(&mut self, label: &'static str, shader: wgpu::ShaderModuleDescriptor) -> Result<(), Error> {
+        let compiled = match &shader.source {
+            wgpu::ShaderSource::Wgsl(source) => {
+                let text = SourceText::File { name: shader.label.unwrap_or(label), source };
+                let module = shader::parse_wgsl(label, text)?;
+                shader::create_module(self.compute(), label, module, text)?
+            },
+            wgpu::ShaderSource::Naga(module) => {
+                shader::create_module(self.compute(), label, module.as_ref().clone(), SourceText::None)?
+            },
+            _ => {
+                let module = self.compute().device.create_shader_module(shader);
+                self.storage_mut().modules.insert(label, module);
+                return Ok(());
+            }
+        };
+
+        self.storage_mut().insert_module(label, compiled);
+        Ok(())
+    }
+
+    /// Runs `name` through `Storage::preprocessor` and compiles the result.
+    /// Errors are reported against the original files and lines.
+    fn add_module_preprocessed(&mut self, label: &'static str, name: &str) -> Result<(), Error> {
+        let source = self.storage().preprocessor.process(name)?;
+        let text = SourceText::Preprocessed(&source);
+        let module = shader::parse_wgsl(label, text)?;
+        let compiled = shader::create_module(self.compute(), label, module, text)?;
+        self.storage_mut().insert_module(label, compiled);
+        Ok(())
+    }
+
+    /// Compiles GLSL for a single shader `stage` through naga's GLSL front end.
+    /// `defines` act as `#define name value` lines at the top of the source.
+    fn add_module_glsl(&mut self, label: &'static str, source: &str, stage: naga::ShaderStage, defines: &[(&str, &str)]) -> Result<(), Error> {
+        let module = shader::parse_glsl(label, source, stage, defines)?;
+        let compiled = shader::create_module(self.compute(), label, module, SourceText::File { name: label, source })?;
+        self.storage_mut().insert_module(label, compiled);
+        Ok(())
+    }
+
+    /// Compiles a SPIR-V binary through naga's SPIR-V front end.
+    fn add_module_spirv(&mut self, label: &'static str, bytes: &[u8]) -> Result<(), Error> {
+        let module = shader::parse_spirv(label, bytes)?;
+        self.add_naga_module(label, module)
+    }
+
+    /// Validates naga IR and creates a shader module from it.
+    fn add_naga_module(&mut self, label: &'static str, module: naga::Module) -> Result<(), Error> {
+        let compiled = shader::create_module(self.compute(), label, module, SourceText::None)?;
+        self.storage_mut().insert_module(label, compiled);
+        Ok(())
     }
     
     fn add_staging_buffer(&mut self, label: &'static str) {
@@ -143,12 +404,306 @@ pub trait ComputeProgram {
             view_formats: &[]
         });
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-
-        self.storage_mut().texture_views.insert(label, view);
-        self.storage_mut().textures.insert(label, texture);
+        self.storage_mut().insert_texture(label, texture, false);
     }
     
+    /// Creates a 2D texture with one mip filled with `data`, which holds every layer of
+    /// `size` one after another.
+    fn add_texture_init<T: Pod>(
+        &mut self,
+        label: &'static str,
+        usage: wgpu::TextureUsages,
+        format: wgpu::TextureFormat,
+        size: wgpu::Extent3d,
+        data: &[T]
+    ) -> Result<(), Error> {
+        self.add_texture_init_mips(label, usage, format, size, 1, data)
+    }
+
+    /// Creates a 2D texture with `mip_level_count` mips filled with `data`. Like
+    /// `wgpu::util::TextureDataOrder::LayerMajor`, `data` holds every mip of the first
+    /// layer, then every mip of the second layer and so on.
+    fn add_texture_init_mips<T: Pod>(
+        &mut self,
+        label: &'static str,
+        usage: wgpu::TextureUsages,
+        format: wgpu::TextureFormat,
+        size: wgpu::Extent3d,
+        mip_level_count: u32,
+        data: &[T]
+    ) -> Result<(), Error> {
+        let texture = self.compute().device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size,
+            usage: usage | wgpu::TextureUsages::COPY_DST,
+            format,
+            dimension: wgpu::TextureDimension::D2,
+            mip_level_count,
+            sample_count: 1,
+            view_formats: &[]
+        });
+
+        texture::write_all(&self.compute().queue, label, &texture, TextureDataOrder::LayerMajor, bytemuck::cast_slice(data))?;
+
+        self.storage_mut().insert_texture(label, texture, false);
+        Ok(())
+    }
+
+    /// Creates a 2D texture from a `(height, width)` array or a 3D texture from a
+    /// `(depth, height, width)` array. Each element is one texel, so `T` has to match the
+    /// texel size of `format`, e.g. `[f32; 4]` for `Rgba32Float`.
+    #[cfg(feature = "ndarray")]
+    fn add_texture_from_array<T: Pod, D: ndarray::Dimension>(
+        &mut self,
+        label: &'static str,
+        usage: wgpu::TextureUsages,
+        format: wgpu::TextureFormat,
+        array: ndarray::ArrayView<T, D>
+    ) -> Result<(), Error> {
+        arrays::create_texture(self, label, usage, format, array)
+    }
+
+    /// Reads one mip of a texture into a `(height, width)` array, or a
+    /// `(depth or layers, height, width)` array for 3D and array textures. Needs the
+    /// staging buffer of `add_texture_staging`.
+    #[cfg(feature = "ndarray")]
+    fn read_texture_array<T: Pod, D: ndarray::Dimension>(&self, label: &'static str, mip: u32) -> Result<ndarray::Array<T, D>, Error> {
+        arrays::read_texture(self, label, mip)
+    }
+
+    /// Writes tightly packed texels into a region of a texture created with `COPY_DST`.
+    /// Compressed formats take whole blocks.
+    fn write_texture<T: Pod>(&self, label: &'static str, region: TextureRegion, data: &[T]) -> Result<(), Error> {
+        let texture = &self.storage().textures[label];
+        let data: &[u8] = bytemuck::cast_slice(data);
+
+        let (layout, copy_size, len) = texture::upload_layout(label, texture, &region)?;
+
+        if !texture.usage().contains(wgpu::TextureUsages::COPY_DST) {
+            return Err(Error::Texture { label, message: "the texture was not created with `COPY_DST`".into() });
+        }
+
+        if data.len() != len {
+            return Err(Error::Texture {
+                label,
+                message: format!("expected {} bytes of texel data, got {}", len, data.len())
+            });
+        }
+
+        self.compute().queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: region.mip_level,
+                origin: region.origin,
+                aspect: wgpu::TextureAspect::All
+            },
+            data,
+            layout,
+            copy_size
+        );
+
+        Ok(())
+    }
+
+    /// Decodes a PNG or JPEG image (and OpenEXR or Radiance HDR with the `hdr` feature)
+    /// into a new 2D texture of `format`. Pick `Rgba8UnormSrgb` for colour images and
+    /// `Rgba8Unorm` for data such as normal maps; `Bgra8Unorm`, `R8Unorm`, `R32Float`,
+    /// `Rg32Float`, `Rgba16Float` and `Rgba32Float` work too. Formats with fewer channels
+    /// keep the first ones of the image, e.g. `R32Float` takes the red channel.
+    #[cfg(feature = "image")]
+    fn add_texture_from_image<'a>(
+        &mut self,
+        label: &'static str,
+        source: impl Into<ImageSource<'a>>,
+        usage: wgpu::TextureUsages,
+        format: wgpu::TextureFormat
+    ) -> Result<(), Error> {
+        let image = images::load(label, source.into())?;
+        let size = wgpu::Extent3d { width: image.width(), height: image.height(), depth_or_array_layers: 1 };
+        let texels = images::to_texels(label, image, format)?;
+
+        self.add_texture_init(label, usage, format, size, &texels)
+    }
+
+    /// Reads mip 0 of a texture back and saves it as a PNG, creating its staging buffer
+    /// if needed. Supports the formats of `add_texture_from_image`; float textures are
+    /// clamped to `0..=1`.
+    #[cfg(feature = "image")]
+    fn save_texture_png(&mut self, label: &'static str, path: impl AsRef<Path>) -> Result<(), Error> {
+        images::save_texture(self, label, path.as_ref(), ::image::ImageFormat::Png)
+    }
+
+    /// Saves mip 0 of a texture as 32-bit float OpenEXR, keeping values outside `0..=1`.
+    #[cfg(feature = "hdr")]
+    fn save_texture_exr(&mut self, label: &'static str, path: impl AsRef<Path>) -> Result<(), Error> {
+        images::save_texture(self, label, path.as_ref(), ::image::ImageFormat::OpenExr)
+    }
+
+    /// Saves mip 0 of a texture as Radiance HDR. The format has no alpha channel.
+    #[cfg(feature = "hdr")]
+    fn save_texture_hdr(&mut self, label: &'static str, path: impl AsRef<Path>) -> Result<(), Error> {
+        images::save_texture(self, label, path.as_ref(), ::image::ImageFormat::Hdr)
+    }
+
+    /// Creates a texture from a KTX2 file with all its mips, array layers and cube faces.
+    /// Cube maps get a `Cube` or `CubeArray` view. Zstandard supercompression is decoded;
+    /// compressed formats the device lacks the `TEXTURE_COMPRESSION_*` feature for are
+    /// reported as `Error::Texture` instead of being decompressed.
+    #[cfg(feature = "ktx2")]
+    fn add_texture_from_ktx2<'a>(&mut self, label: &'static str, source: impl Into<ImageSource<'a>>, usage: wgpu::TextureUsages) -> Result<(), Error> {
+        let file = containers::read_ktx2(label, &source.into().read()?)?;
+        containers::create_texture(self, label, usage, file)
+    }
+
+    /// Creates a texture from a DDS file, like `add_texture_from_ktx2`. Both legacy and
+    /// DX10 headers are supported.
+    #[cfg(feature = "dds")]
+    fn add_texture_from_dds<'a>(&mut self, label: &'static str, source: impl Into<ImageSource<'a>>, usage: wgpu::TextureUsages) -> Result<(), Error> {
+        let file = containers::read_dds(label, &source.into().read()?)?;
+        containers::create_texture(self, label, usage, file)
+    }
+
+    /// Converts the equirectangular float texture `equirect` (e.g. an HDR sky loaded with
+    /// `add_texture_from_image`) into cube maps for skyboxes and image-based lighting,
+    /// stored under labels derived from `label`: the environment itself with a full mip
+    /// chain, GGX-prefiltered specular mips and diffuse irradiance.
+    fn add_environment_maps(&mut self, label: &'static str, equirect: &'static str, options: EnvironmentMapOptions) -> Result<EnvironmentMaps, Error> {
+        environment::create(self, label, equirect, options)
+    }
+
+    /// Sets up a parallel reduction of the `f32`, `u32` or `i32` buffer `input` into the
+    /// one-element buffer `label`, compiling its kernels and creating its intermediate
+    /// buffers under labels derived from `label`. Arg reductions store the `u32` index.
+    /// Subgroup operations are used when the device has `Features::SUBGROUP`.
+    ///
+    /// The number of elements is fixed here; call it again if `input` is replaced.
+    fn add_reduction(&mut self, label: &'static str, input: &'static str, element: ElementType, reduction: Reduction) -> Result<(), Error> {
+        primitives::reduce::create(self, label, input, element, reduction)
+    }
+
+    /// Records the passes of the reduction `label` into `encoder`.
+    fn reduce(&self, encoder: &mut wgpu::CommandEncoder, label: &'static str) -> Result<(), Error> {
+        primitives::reduce::record(self, encoder, label)
+    }
+
+    /// Sets up an inclusive or exclusive prefix sum of the `f32`, `u32` or `i32` buffer
+    /// `input` into the buffer `label` of the same length. Arrays of any length are
+    /// scanned in blocks, with the block totals scanned recursively and added back.
+    ///
+    /// The number of elements is fixed here; call it again if `input` is replaced.
+    fn add_scan(&mut self, label: &'static str, input: &'static str, element: ElementType, scan: Scan) -> Result<(), Error> {
+        primitives::scan::create(self, label, input, element, scan)
+    }
+
+    /// Records the passes of the prefix sum `label` into `encoder`.
+    fn scan(&self, encoder: &mut wgpu::CommandEncoder, label: &'static str) -> Result<(), Error> {
+        primitives::scan::record(self, encoder, label)
+    }
+
+    /// Sets up a stable radix sort of the `u32` buffer `keys` in place, moving the `u32`
+    /// or `f32` buffer `values` along with it if given. Only the key bits in `bits` are
+    /// compared, e.g. `0..16` for keys below 65536. Both buffers need `STORAGE`; the
+    /// temporary buffers are created under labels derived from `label`.
+    ///
+    /// The number of keys is fixed here; call it again if `keys` is replaced.
+    fn add_radix_sort(&mut self, label: &'static str, keys: &'static str, values: Option<&'static str>, bits: Range<u32>) -> Result<(), Error> {
+        primitives::radix_sort::create(self, label, keys, values, bits)
+    }
+
+    /// Records the passes of the radix sort `label` into `encoder`.
+    fn radix_sort(&self, encoder: &mut wgpu::CommandEncoder, label: &'static str) -> Result<(), Error> {
+        primitives::radix_sort::record(self, encoder, label)
+    }
+
+    /// Sets up an in-place bitonic sort of `buffer`, ordered by the WGSL `less` function
+    /// of `sort`. Elements can be `f32`s or structs compared by any of their fields, and
+    /// the buffer can have any length. The sort is not stable. Stages within a tile of
+    /// 512 elements run in workgroup memory, so with the default limits elements can be
+    /// at most 32 bytes.
+    ///
+    /// The number of elements is fixed here; call it again if `buffer` is replaced.
+    fn add_bitonic_sort(&mut self, label: &'static str, buffer: &'static str, sort: BitonicSort) -> Result<(), Error> {
+        primitives::bitonic_sort::create(self, label, buffer, sort)
+    }
+
+    /// Records the steps of the bitonic sort `label` into `encoder`.
+    fn bitonic_sort(&self, encoder: &mut wgpu::CommandEncoder, label: &'static str) -> Result<(), Error> {
+        primitives::bitonic_sort::record(self, encoder, label)
+    }
+
+    /// Creates a staging buffer for reading a texture back with `read_texture`. The texture
+    /// needs `TextureUsages::COPY_SRC`.
+    fn add_texture_staging(&mut self, label: &'static str) -> Result<(), Error> {
+        let layout = texture::ImageLayout::of_mip(label, &self.storage().textures[label], 0)?;
+
+        let buffer = self.compute().device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            size: layout.padded_size(),
+            mapped_at_creation: false
+        });
+
+        self.storage_mut().texture_staging_buffers.insert(label, buffer);
+        Ok(())
+    }
+
+    /// Reads one mip of one layer of a texture, blocking until the copy is done. For 3D
+    /// textures `layer` is the depth slice. Rows are returned tightly packed, without the
+    /// padding `copy_texture_to_buffer` requires.
+    fn read_texture<T: Pod>(&self, label: &'static str, mip: u32, layer: u32) -> Result<Vec<T>, Error> {
+        let texture = &self.storage().textures[label];
+        let staging_buffer = &self.storage().texture_staging_buffers[label];
+
+        let layout = texture::ImageLayout::of_mip(label, texture, mip)?;
+        texture::check_layer(label, texture, mip, layer)?;
+
+        if !texture.usage().contains(wgpu::TextureUsages::COPY_SRC) {
+            return Err(Error::Texture { label, message: "the texture was not created with `COPY_SRC`".into() });
+        }
+
+        if layout.size() % std::mem::size_of::<T>() != 0 {
+            return Err(Error::Texture {
+                label,
+                message: format!("rows of {} bytes are not made of `{}`s", layout.bytes_per_row, std::any::type_name::<T>())
+            });
+        }
+
+        let mut encoder = self.compute().device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: None
+        });
+
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: mip,
+                origin: wgpu::Origin3d { x: 0, y: 0, z: layer },
+                aspect: wgpu::TextureAspect::All
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: staging_buffer,
+                layout: layout.buffer_layout()
+            },
+            wgpu::Extent3d { width: layout.width, height: layout.height, depth_or_array_layers: 1 }
+        );
+
+        let submission = self.compute().queue.submit(Some(encoder.finish()));
+
+        let (sender, receiver) = flume::bounded(1);
+        let slice = staging_buffer.slice(..layout.padded_size());
+        slice.map_async(wgpu::MapMode::Read, move |v| { let _ = sender.send(v); });
+
+        self.compute().device.poll(wgpu::Maintain::WaitForSubmissionIndex(submission));
+
+        let mapped = receiver.recv().unwrap_or(Err(wgpu::BufferAsyncError));
+        mapped.map_err(|source| Error::Map { label, source })?;
+
+        let mut output = vec![T::zeroed(); layout.size() / std::mem::size_of::<T>()];
+        layout.strip_padding(&slice.get_mapped_range(), bytemuck::cast_slice_mut(&mut output));
+
+        staging_buffer.unmap();
+        Ok(output)
+    }
+
     fn add_sampler(&mut self, label: &'static str, descriptor: wgpu::SamplerDescriptor) {
         let sampler = self.compute().device.create_sampler(&descriptor);
         self.storage_mut().samplers.insert(label, sampler);
@@ -196,6 +751,7 @@ pub trait ComputeProgram {
                     });
                 },
                 BindGroupItem::Texture { label } => {
+                    let view_dimension = self.storage().texture_view_dimension(label);
                     let sample_type = self.storage().textures[label].format().sample_type(None, None).unwrap();
 
                     bind_group_layout_entries.push(wgpu::BindGroupLayoutEntry {
@@ -203,7 +759,7 @@ pub trait ComputeProgram {
                         visibility: wgpu::ShaderStages::all(),
                         ty: wgpu::BindingType::Texture { 
                             sample_type,
-                            view_dimension: wgpu::TextureViewDimension::D2, 
+                            view_dimension,
                             multisampled: false
                         },
                         count: None
@@ -215,12 +771,13 @@ pub trait ComputeProgram {
                     });
                 },
                 BindGroupItem::TextureView { label, sample_type } => {
+                    let view_dimension = self.storage().texture_view_dimension(label);
                     bind_group_layout_entries.push(wgpu::BindGroupLayoutEntry {
                         binding: i as u32,
                         visibility: wgpu::ShaderStages::all(),
                         ty: wgpu::BindingType::Texture { 
                             sample_type: *sample_type,
-                            view_dimension: wgpu::TextureViewDimension::D2, 
+                            view_dimension,
                             multisampled: false
                         },
                         count: None
@@ -232,6 +789,7 @@ pub trait ComputeProgram {
                     });
                 },
                 BindGroupItem::StorageTexture { label, access } => {
+                    let view_dimension = self.storage().texture_view_dimension(label);
                     let format = self.storage().textures[label].format();
                     bind_group_layout_entries.push(wgpu::BindGroupLayoutEntry {
                         binding: i as u32,
@@ -239,7 +797,25 @@ pub trait ComputeProgram {
                         ty: wgpu::BindingType::StorageTexture { 
                             access: *access, 
                             format, 
-                            view_dimension: wgpu::TextureViewDimension::D2
+                            view_dimension
+                        },
+                        count: None
+                    });
+
+                    bind_group_entries.push(wgpu::BindGroupEntry {
+                        binding: i as u32,
+                        resource: wgpu::BindingResource::TextureView(&self.storage().texture_views[label])
+                    });
+                },
+                BindGroupItem::StorageTextureView { label, access, format } => {
+                    let view_dimension = self.storage().texture_view_dimension(label);
+                    bind_group_layout_entries.push(wgpu::BindGroupLayoutEntry {
+                        binding: i as u32,
+                        visibility: wgpu::ShaderStages::COMPUTE | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: *access,
+                            format: *format,
+                            view_dimension
                         },
                         count: None
                     });
@@ -282,6 +858,186 @@ pub trait ComputeProgram {
         self.storage_mut().bind_group_layouts.insert(label, bind_group_layout);
     }
     
+    /// Records a compute pass running `kernel` over `invocations` threads, with the
+    /// workgroup counts derived from the kernel's `@workgroup_size`. `bind_groups` are
+    /// bound in order starting at group 0.
+    ///
+    /// One-dimensional dispatches larger than `max_compute_workgroups_per_dimension` are
+    /// spread over the y and z dimensions; such kernels must compute their index from
+    /// `workgroup_id` and `num_workgroups` and ignore indices past the end.
+    fn dispatch(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        kernel: &'static str,
+        bind_groups: &[&'static str],
+        invocations: impl Into<Invocations>
+    ) -> Result<(), Error> {
+        let workgroup_size = *self.storage().workgroup_sizes.get(kernel).ok_or_else(|| Error::Dispatch {
+            label: kernel,
+            message: "the kernel's workgroup size is unknown; it must come from a module compiled through naga".into()
+        })?;
+
+        let max_per_dimension = self.compute().device.limits().max_compute_workgroups_per_dimension;
+        let [x, y, z] = dispatch::workgroup_counts(invocations.into(), workgroup_size, max_per_dimension)
+            .map_err(|message| Error::Dispatch { label: kernel, message })?;
+
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: None,
+            timestamp_writes: None
+        });
+
+        cpass.set_pipeline(&self.storage().compute_pipelines[kernel]);
+        for (i, bind_group) in bind_groups.iter().enumerate() {
+            cpass.set_bind_group(i as u32, &self.storage().bind_groups[bind_group], &[]);
+        }
+        cpass.dispatch_workgroups(x, y, z);
+
+        Ok(())
+    }
+
+    /// Uploads data, runs `dispatches` in order and reads `readbacks` back, waiting for
+    /// the GPU to finish. Staging buffers are created for the read back buffers as needed.
+    /// Uploads are written from the start of their buffer and must fit in it with a length
+    /// that is a multiple of `COPY_BUFFER_ALIGNMENT`.
+    ///
+    /// Returns the contents of each buffer in `readbacks`, in order.
+    fn run_blocking<T: Pod>(
+        &mut self,
+        uploads: &[(&'static str, &[u8])],
+        dispatches: &[Dispatch],
+        readbacks: &[&'static str]
+    ) -> Result<Vec<Vec<T>>, Error> {
+        let counts = readbacks
+            .iter()
+            .map(|label| buffer::element_count::<T>(self.storage(), label))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (label, data) in uploads {
+            let range = 0..data.len() as u64;
+            buffer::check_range(label, &range, self.storage().buffers[label].size(), wgpu::COPY_BUFFER_ALIGNMENT)?;
+        }
+
+        for (label, data) in uploads {
+            self.compute().queue.write_buffer(&self.storage().buffers[label], 0, data);
+        }
+
+        for label in readbacks {
+            let size = self.storage().buffers[label].size();
+            if self.storage().staging_buffers.get(label).map(wgpu::Buffer::size) != Some(size) {
+                self.add_staging_buffer(label);
+            }
+        }
+
+        let mut encoder = self.compute().device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: None
+        });
+
+        for dispatch in dispatches {
+            self.dispatch(&mut encoder, dispatch.kernel, dispatch.bind_groups, dispatch.invocations)?;
+        }
+
+        for label in readbacks {
+            self.copy_buffer_to_staging(&mut encoder, label);
+        }
+
+        self.compute().queue.submit(Some(encoder.finish()));
+
+        for label in readbacks {
+            self.prepare_staging_buffer(label);
+        }
+
+        self.compute().device.poll(wgpu::Maintain::Wait);
+
+        // Every readback is drained and unmapped even after a failure, so the staging
+        // buffers can be mapped again by the next run
+        let mut outputs = Vec::with_capacity(readbacks.len());
+        let mut error = None;
+
+        for (label, count) in readbacks.iter().zip(counts) {
+            let staging_buffer = &self.storage().staging_buffers[label];
+
+            if let Err(source) = self.storage().staging_receivers[label].recv().unwrap() {
+                error.get_or_insert(Error::Map { label, source });
+                continue;
+            }
+
+            let mut output = vec![T::zeroed(); count];
+            buffer::read_mapped(staging_buffer, 0, &mut output);
+
+            staging_buffer.unmap();
+            outputs.push(output);
+        }
+
+        match error {
+            Some(error) => Err(error),
+            None => Ok(outputs)
+        }
+    }
+
+    /// Reads a buffer back without blocking the calling thread.
+    ///
+    /// The copy is submitted immediately into a temporary staging buffer, and a background
+    /// thread shared by every read of the program polls the device until it completes. The
+    /// returned future does not borrow the program and can be awaited from any executor.
+    fn read_buffer<T: Pod>(&self, label: &'static str) -> impl Future<Output = Result<Vec<T>, Error>> + Send + 'static {
+        let setup = buffer::element_count::<T>(self.storage(), label).map(|count| {
+            // Empty slices cannot be mapped
+            if count == 0 {
+                return None;
+            }
+
+            let device = self.compute().device.clone();
+            let buffer = &self.storage().buffers[label];
+
+            let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                size: buffer.size(),
+                mapped_at_creation: false
+            });
+
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: None
+            });
+            encoder.copy_buffer_to_buffer(buffer, 0, &staging_buffer, 0, buffer.size());
+            let submission = self.compute().queue.submit(Some(encoder.finish()));
+
+            let (sender, receiver) = flume::bounded(1);
+            staging_buffer.slice(..).map_async(wgpu::MapMode::Read, move |v| { let _ = sender.send(v); });
+
+            let poller = self.storage().read_poller.get_or_init(|| {
+                let (sender, submissions) = flume::unbounded();
+
+                // Exits once the storage, and with it the sender, is dropped
+                std::thread::spawn(move || {
+                    for submission in submissions.iter() {
+                        device.poll(wgpu::Maintain::WaitForSubmissionIndex(submission));
+                    }
+                });
+
+                sender
+            });
+            let _ = poller.send(submission);
+
+            Some((staging_buffer, receiver, count))
+        });
+
+        async move {
+            let Some((staging_buffer, receiver, count)) = setup? else {
+                return Ok(Vec::new());
+            };
+
+            let mapped = receiver.recv_async().await.unwrap_or(Err(wgpu::BufferAsyncError));
+            mapped.map_err(|source| Error::Map { label, source })?;
+
+            let mut output = vec![T::zeroed(); count];
+            buffer::read_mapped(&staging_buffer, 0, &mut output);
+
+            staging_buffer.unmap();
+            Ok(output)
+        }
+    }
+
     fn copy_buffer_to_buffer_full(&self, encoder: &mut wgpu::CommandEncoder, buffer_a: &'static str, buffer_b: &'static str) {
         encoder.copy_buffer_to_buffer(
             &self.storage().buffers[buffer_a], 
@@ -313,20 +1069,136 @@ pub trait ComputeProgram {
         self.storage().staging_receivers[label].recv().unwrap().unwrap();
 
         // Read data
-        {
-            let data_size = std::mem::size_of::<T>();
-            let data_len = dst.len();
-            let num_bytes = (data_len * data_size) as u64;
-
-            let dst = bytemuck::cast_slice_mut(dst);
-            let data = self.storage().staging_buffers[label].slice(..num_bytes).get_mapped_range();
-            dst.copy_from_slice(&data);
-        }
+        buffer::read_mapped(&self.storage().staging_buffers[label], 0, dst);
 
         // Unmap for the GPU to use again
         self.storage().staging_buffers[label].unmap();
     }
     
+    /// Creates a ring of `frames` staging buffers for a buffer, so that a new readback can
+    /// start every frame while older ones are still in flight or mapped.
+    fn add_staging_ring(&mut self, label: &'static str, frames: usize) {
+        let ring = StagingRing::new(&self.compute().device, self.storage().buffers[label].size(), frames);
+        self.storage_mut().staging_rings.insert(label, ring);
+    }
+
+    /// Records a copy of a buffer into the next free staging buffer of its ring. Returns
+    /// `false`, skipping this frame's readback, when all of them are still in use.
+    fn copy_buffer_to_staging_ring(&mut self, encoder: &mut wgpu::CommandEncoder, label: &'static str) -> Result<bool, Error> {
+        let storage = self.storage_mut();
+        let ring = staging::ring_mut(&mut storage.staging_rings, label)?;
+        Ok(ring.copy(encoder, &storage.buffers[label]))
+    }
+
+    /// Starts mapping the staging buffers copied into since the last call. Call this after
+    /// submitting the encoder passed to `copy_buffer_to_staging_ring`.
+    fn prepare_staging_ring(&mut self, label: &'static str) -> Result<(), Error> {
+        staging::ring_mut(&mut self.storage_mut().staging_rings, label)?.prepare();
+        Ok(())
+    }
+
+    /// Polls the device without blocking and reads the most recent completed readback of
+    /// a buffer into `dst`. Returns `false` if nothing newer than the last read is ready.
+    fn try_read_latest<T: Pod>(&mut self, label: &'static str, dst: &mut [T]) -> Result<bool, Error> {
+        buffer::element_count::<T>(self.storage(), label)?;
+        self.compute().device.poll(wgpu::Maintain::Poll);
+        staging::ring_mut(&mut self.storage_mut().staging_rings, label)?.try_read_latest(label, dst)
+    }
+
+    /// Copies `range` of a buffer into the same range of its staging buffer.
+    fn copy_buffer_to_staging_range(&self, encoder: &mut wgpu::CommandEncoder, label: &'static str, range: Range<u64>) -> Result<(), Error> {
+        let buffer = &self.storage().buffers[label];
+        let staging_buffer = &self.storage().staging_buffers[label];
+
+        buffer::check_range(label, &range, buffer.size().min(staging_buffer.size()), wgpu::COPY_BUFFER_ALIGNMENT)?;
+
+        encoder.copy_buffer_to_buffer(buffer, range.start, staging_buffer, range.start, range.end - range.start);
+        Ok(())
+    }
+
+    /// Like `prepare_staging_buffer`, but only maps `range` of the staging buffer.
+    fn prepare_staging_range(&mut self, label: &'static str, range: Range<u64>) -> Result<(), Error> {
+        let staging_buffer = &self.storage().staging_buffers[label];
+        buffer::check_range(label, &range, staging_buffer.size(), wgpu::MAP_ALIGNMENT)?;
+
+        let sender = self.storage().staging_senders[label].clone();
+        staging_buffer.slice(range.clone()).map_async(wgpu::MapMode::Read, move |v| sender.send(v).unwrap());
+
+        self.storage_mut().mapped_staging_ranges.insert(label, range);
+        Ok(())
+    }
+
+    /// Like `read_staging_buffer`, but reads `dst.len()` elements starting `offset` bytes
+    /// into the staging buffer. The range, rounded up to a multiple of
+    /// `COPY_BUFFER_ALIGNMENT` bytes, must lie within the range passed to
+    /// `prepare_staging_range`.
+    fn read_staging_range<T: Pod>(&mut self, label: &'static str, offset: u64, dst: &mut [T]) -> Result<(), Error> {
+        let range = offset..offset + buffer::aligned_size(std::mem::size_of_val(dst) as u64);
+        let mapped = self.storage().mapped_staging_ranges.get(label).cloned().ok_or_else(|| Error::Range {
+            label,
+            message: "no range of the staging buffer was mapped by prepare_staging_range".into()
+        })?;
+
+        buffer::check_range(label, &range, self.storage().staging_buffers[label].size(), wgpu::MAP_ALIGNMENT)?;
+        if range.start < mapped.start || range.end > mapped.end {
+            return Err(Error::Range { label, message: format!("{:?} is outside the mapped range {:?}", range, mapped) });
+        }
+        buffer::element_count::<T>(self.storage(), label)?;
+
+        self.storage_mut().mapped_staging_ranges.remove(label);
+        let staging_buffer = &self.storage().staging_buffers[label];
+
+        // Wait for the mapping to finish
+        let mapped = self.storage().staging_receivers[label].recv().unwrap();
+        mapped.map_err(|source| Error::Map { label, source })?;
+
+        // Read data
+        buffer::read_mapped(staging_buffer, offset, dst);
+
+        // Unmap for the GPU to use again
+        staging_buffer.unmap();
+        Ok(())
+    }
+
+    /// Writes `data` into a buffer starting `offset` bytes in.
+    fn write_buffer<T: Pod>(&self, label: &'static str, offset: u64, data: &[T]) -> Result<(), Error> {
+        let buffer = &self.storage().buffers[label];
+        let range = offset..offset + std::mem::size_of_val(data) as u64;
+        buffer::check_range(label, &range, buffer.size(), wgpu::COPY_BUFFER_ALIGNMENT)?;
+
+        self.compute().queue.write_buffer(buffer, offset, bytemuck::cast_slice(data));
+        Ok(())
+    }
+
+    /// Records a write of `data` into a buffer through `Storage::uploads`. Call
+    /// `finish_staged_writes` before submitting `encoder` and `recall_staged_writes` after.
+    fn write_staged<T: Pod>(&mut self, encoder: &mut wgpu::CommandEncoder, label: &'static str, offset: u64, data: &[T]) -> Result<(), Error> {
+        let device = self.compute().device.clone();
+        let storage = self.storage_mut();
+        let buffer = &storage.buffers[label];
+
+        let range = offset..offset + std::mem::size_of_val(data) as u64;
+        buffer::check_range(label, &range, buffer.size(), wgpu::COPY_BUFFER_ALIGNMENT)?;
+
+        storage.uploads.write(&device, encoder, buffer, offset, bytemuck::cast_slice(data));
+        Ok(())
+    }
+
+    /// Unmaps the upload chunks written by `write_staged` so their copies can execute.
+    fn finish_staged_writes(&mut self) {
+        self.storage_mut().uploads.finish();
+    }
+
+    /// Recycles the upload chunks once the GPU is done with them. Call after submitting.
+    ///
+    /// Chunks only return to the belt when their mapping callbacks run, which happens
+    /// while the device is polled. This polls without blocking, so chunks of earlier
+    /// submissions that have finished are available to the next `write_staged`.
+    fn recall_staged_writes(&mut self) {
+        self.storage_mut().uploads.recall();
+        self.compute().device.poll(wgpu::Maintain::Poll);
+    }
+
     fn add_compute_pipelines(
         &mut self,
         module: &'static str,
@@ -334,35 +1206,48 @@ pub trait ComputeProgram {
         kernels: &[ComputeKernel],
         push_constant_ranges: &[wgpu::PushConstantRange],
         compilation_options: Option<wgpu::PipelineCompilationOptions>
-    ) {
-        let bind_group_layouts: Vec<_> = bind_groups
-            .iter()
-            .map(|x| &self.storage().bind_group_layouts[x])
-            .collect();
+    ) -> Result<(), Error> {
+        let (constants, zero_initialize_workgroup_memory) = compilation_options
+            .map(|options| (options.constants.clone(), options.zero_initialize_workgroup_memory))
+            .unwrap_or((HashMap::new(), true));
 
-        let pipeline_layout = self.compute().device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: None,
-            bind_group_layouts: &bind_group_layouts,
-            push_constant_ranges
-        });
-
-        let empty_map = HashMap::new();
-        let compilation_options = compilation_options.unwrap_or(wgpu::PipelineCompilationOptions {
-            zero_initialize_workgroup_memory: true,
-            constants: &empty_map
-        });  
-        
         for kernel in kernels {
-            let pipeline = self.compute().device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                label: None,
-                layout: Some(&pipeline_layout),
-                module: &self.storage().modules[module],
-                entry_point: &kernel.entry_point,
-                compilation_options: compilation_options.clone()
-            });
+            let mut kernel_constants = constants.clone();
+            kernel_constants.extend(kernel.constants.iter().map(|(name, value)| (name.to_string(), *value)));
+
+            let workgroup_size = match self.storage().naga_modules.get(module) {
+                Some(naga_module) => {
+                    pipeline::check_constants(kernel.label, naga_module, &kernel_constants)?;
+                    pipeline::workgroup_size(naga_module, kernel.entry_point)
+                },
+                None => None
+            };
+
+            let params = ComputePipelineParams {
+                module,
+                bind_groups: bind_groups.to_vec(),
+                entry_point: kernel.entry_point,
+                push_constant_ranges: push_constant_ranges.to_vec(),
+                constants: kernel_constants,
+                zero_initialize_workgroup_memory
+            };
 
-            self.storage_mut().compute_pipelines.insert(&kernel.label, pipeline);
+            let pipeline = pipeline::create_compute_pipeline(
+                &self.compute().device,
+                self.storage(),
+                &self.storage().modules[module],
+                &params
+            );
+
+            self.storage_mut().compute_pipelines.insert(kernel.label, pipeline);
+            self.storage_mut().compute_pipeline_params.insert(kernel.label, params);
+
+            if let Some(workgroup_size) = workgroup_size {
+                self.storage_mut().workgroup_sizes.insert(kernel.label, workgroup_size);
+            }
         }
+
+        Ok(())
     }
 
     fn add_render_pipelines_2(
@@ -370,6 +1255,7 @@ pub trait ComputeProgram {
         
     ) {}
     
+    #[allow(clippy::too_many_arguments)]
     fn add_render_pipelines(
         &mut self,
         module: &'static str,
@@ -381,44 +1267,172 @@ pub trait ComputeProgram {
         vertex_compilation_options: Option<wgpu::PipelineCompilationOptions>,
         fragment_compilation_options: Option<wgpu::PipelineCompilationOptions>
     ) {
-        let bind_group_layouts: Vec<_> = bind_groups
+        let (vertex_constants, vertex_zero_initialize_workgroup_memory) = vertex_compilation_options
+            .map(|options| (options.constants.clone(), options.zero_initialize_workgroup_memory))
+            .unwrap_or((HashMap::new(), true));
+
+        let (fragment_constants, fragment_zero_initialize_workgroup_memory) = fragment_compilation_options
+            .map(|options| (options.constants.clone(), options.zero_initialize_workgroup_memory))
+            .unwrap_or((HashMap::new(), true));
+
+        let vertex_buffer_layouts: Vec<_> = vertex_buffer_layouts
             .iter()
-            .map(|x| &self.storage().bind_group_layouts[x])
+            .map(|layout| VertexBufferLayoutParams {
+                array_stride: layout.array_stride,
+                step_mode: layout.step_mode,
+                attributes: layout.attributes.to_vec()
+            })
             .collect();
 
-        let pipeline_layout = self.compute().device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: None,
-            bind_group_layouts: &bind_group_layouts,
-            push_constant_ranges
+        for kernel in kernels {
+            let params = RenderPipelineParams {
+                module,
+                bind_groups: bind_groups.to_vec(),
+                vertex: kernel.vertex,
+                fragment: kernel.fragment,
+                push_constant_ranges: push_constant_ranges.to_vec(),
+                targets: targets.to_vec(),
+                vertex_buffer_layouts: vertex_buffer_layouts.clone(),
+                vertex_constants: vertex_constants.clone(),
+                vertex_zero_initialize_workgroup_memory,
+                fragment_constants: fragment_constants.clone(),
+                fragment_zero_initialize_workgroup_memory
+            };
+
+            let render_pipeline = pipeline::create_render_pipeline(
+                &self.compute().device,
+                self.storage(),
+                &self.storage().modules[module],
+                &params
+            );
+
+            self.storage_mut().render_pipelines.insert(kernel.label, render_pipeline);
+            self.storage_mut().render_pipeline_params.insert(kernel.label, params);
+        }
+    }
+
+    /// Loads a WGSL file through `Storage::preprocessor` and remembers the files it was
+    /// built from, so that `reload_changed_modules` can recompile it when they change.
+    fn add_module_from_path(&mut self, label: &'static str, path: impl AsRef<Path>) -> Result<(), Error> {
+        let name = path.as_ref().to_string_lossy().into_owned();
+        let source = self.storage().preprocessor.process(&name)?;
+        let text = SourceText::Preprocessed(&source);
+        let module = shader::parse_wgsl(label, text)?;
+        let compiled = shader::create_module(self.compute(), label, module, text)?;
+        self.storage_mut().insert_module(label, compiled);
+
+        self.track_module_files(label, ModuleFiles::new(name, &source))
+    }
+
+    #[doc(hidden)]
+    fn track_module_files(&mut self, label: &'static str, files: ModuleFiles) -> Result<(), Error> {
+        #[cfg(feature = "hot-reload")]
+        if let Some(watcher) = &mut self.storage_mut().module_watcher {
+            watcher.watch(&files)?;
+        }
+
+        self.storage_mut().module_files.insert(label, files);
+        Ok(())
+    }
+
+    /// Starts watching the files of modules loaded with `add_module_from_path`, so that
+    /// `reload_changed_modules` returns immediately while nothing has changed.
+    #[cfg(feature = "hot-reload")]
+    fn watch_modules(&mut self) -> Result<(), Error> {
+        let mut watcher = reload::ModuleWatcher::new()?;
+
+        for files in self.storage().module_files.values() {
+            watcher.watch(files)?;
+        }
+
+        self.storage_mut().module_watcher = Some(watcher);
+        Ok(())
+    }
+
+    /// Recompiles every module loaded with `add_module_from_path` whose files changed on
+    /// disk, then rebuilds the pipelines created from it with their original parameters.
+    ///
+    /// Returns the result for each module that was reloaded. A module that fails to
+    /// compile keeps its previous shader module and pipelines.
+    fn reload_changed_modules(&mut self) -> Vec<(&'static str, Result<(), Error>)> {
+        #[cfg(feature = "hot-reload")]
+        if let Some(watcher) = &self.storage().module_watcher {
+            if !watcher.take_events() {
+                return Vec::new();
+            }
+        }
+
+        let changed: Vec<&'static str> = self.storage().module_files
+            .iter()
+            .filter(|(_, files)| files.is_changed())
+            .map(|(label, _)| *label)
+            .collect();
+
+        changed
+            .into_iter()
+            .map(|label| (label, self.reload_module(label)))
+            .collect()
+    }
+
+    /// Recompiles a module loaded with `add_module_from_path` and rebuilds its pipelines.
+    /// Fails for modules that were not loaded from a file.
+    fn reload_module(&mut self, label: &'static str) -> Result<(), Error> {
+        let name = match self.storage().module_files.get(label) {
+            Some(files) => files.name.clone(),
+            None => return Err(Error::Shader {
+                label,
+                message: "the module has no source file to reload, it was not loaded with `add_module_from_path`".to_owned()
+            })
+        };
+        let source = self.storage().preprocessor.process(&name)?;
+
+        // Track the new set of files even if compilation fails, so the module is only
+        // retried after the next change
+        self.track_module_files(label, ModuleFiles::new(name, &source))?;
+
+        let text = SourceText::Preprocessed(&source);
+        let naga_module = shader::parse_wgsl(label, text)?;
+        shader::validate(self.compute(), label, &naga_module, text)?;
+
+        for (kernel, params) in self.storage().compute_pipeline_params.iter().filter(|(_, params)| params.module == label) {
+            pipeline::check_constants(kernel, &naga_module, &params.constants)?;
+        }
+
+        let device = self.compute().device.clone();
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Naga(Cow::Owned(naga_module.clone()))
         });
 
-        let empty_map = HashMap::new();
-        let vertex_compilation_options = vertex_compilation_options.unwrap_or(wgpu::PipelineCompilationOptions { constants: &empty_map, zero_initialize_workgroup_memory: true });
-        let fragment_compilation_options = fragment_compilation_options.unwrap_or(wgpu::PipelineCompilationOptions { constants: &empty_map, zero_initialize_workgroup_memory: true });
+        let compute_pipelines: Vec<_> = self.storage().compute_pipeline_params
+            .iter()
+            .filter(|(_, params)| params.module == label)
+            .map(|(kernel, params)| (*kernel, pipeline::create_compute_pipeline(&device, self.storage(), &module, params)))
+            .collect();
+
+        let render_pipelines: Vec<_> = self.storage().render_pipeline_params
+            .iter()
+            .filter(|(_, params)| params.module == label)
+            .map(|(kernel, params)| (*kernel, pipeline::create_render_pipeline(&device, self.storage(), &module, params)))
+            .collect();
 
-        for kernel in kernels {
-            let render_pipeline = self.compute().device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: None,
-                layout: Some(&pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &self.storage().modules[module],
-                    entry_point: &kernel.vertex,
-                    buffers: vertex_buffer_layouts,
-                    compilation_options: vertex_compilation_options.clone()
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &self.storage().modules[module],
-                    entry_point: &kernel.fragment,
-                    targets,
-                    compilation_options: fragment_compilation_options.clone()
-                }),
-                primitive: wgpu::PrimitiveState::default(),
-                depth_stencil: None,
-                multisample: wgpu::MultisampleState::default(),
-                multiview: None,
-            });
+        if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+            return Err(Error::Shader { label, message: error.to_string() });
+        }
 
-            self.storage_mut().render_pipelines.insert(&kernel.label, render_pipeline);
+        let storage = self.storage_mut();
+        storage.modules.insert(label, module);
+        for (kernel, params) in storage.compute_pipeline_params.iter().filter(|(_, params)| params.module == label) {
+            if let Some(workgroup_size) = pipeline::workgroup_size(&naga_module, params.entry_point) {
+                storage.workgroup_sizes.insert(kernel, workgroup_size);
+            }
         }
+        storage.naga_modules.insert(label, naga_module);
+        storage.compute_pipelines.extend(compute_pipelines);
+        storage.render_pipelines.extend(render_pipelines);
+
+        Ok(())
     }
 }
\ No newline at end of file
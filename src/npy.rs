@@ -0,0 +1,423 @@
+use std::path::Path;
+
+use bytemuck::Pod;
+
+use crate::{ComputeProgram, Error};
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+/// Element types that can be read from and written to NumPy `.npy` files.
+///
+/// Scalars map to their NumPy dtype, e.g. `f32` to `<f4`. Arrays like `[f32; 4]` are
+/// stored as an extra trailing dimension of 4 scalars.
+pub trait NpyElement: Pod {
+    /// The dtype of one scalar, e.g. `<f4`
+    const DESCR: &'static str;
+    /// The number of scalars in one element
+    const SCALARS: usize = 1;
+    /// The trailing dimensions one element adds to the shape
+    fn trailing_shape() -> Vec<usize> {
+        Vec::new()
+    }
+}
+
+macro_rules! npy_element {
+    ($($ty:ty => $descr:literal),*) => {
+        $(impl NpyElement for $ty {
+            const DESCR: &'static str = $descr;
+        })*
+    };
+}
+
+npy_element!(
+    u8 => "|u1", i8 => "|i1",
+    u16 => "<u2", i16 => "<i2",
+    u32 => "<u4", i32 => "<i4", f32 => "<f4",
+    u64 => "<u8", i64 => "<i8", f64 => "<f8"
+);
+
+impl<T: NpyElement, const N: usize> NpyElement for [T; N] where [T; N]: Pod {
+    const DESCR: &'static str = T::DESCR;
+    const SCALARS: usize = N * T::SCALARS;
+
+    fn trailing_shape() -> Vec<usize> {
+        let mut shape = vec![N];
+        shape.extend(T::trailing_shape());
+        shape
+    }
+}
+
+/// The header of a `.npy` file.
+#[derive(Clone, Debug)]
+struct NpyHeader {
+    descr: String,
+    fortran_order: bool,
+    shape: Vec<usize>
+}
+
+fn error(label: &'static str, message: impl Into<String>) -> Error {
+    Error::Npy { label, message: message.into() }
+}
+
+/// Returns the value of `key` in the Python dict literal of a header, up to the next
+/// top-level comma or closing brace.
+fn dict_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header.find(&format!("'{}'", key))? + key.len() + 2;
+    let rest = header[start..].trim_start().strip_prefix(':')?.trim_start();
+
+    let mut depth = 0;
+    let mut quoted = false;
+
+    for (i, c) in rest.char_indices() {
+        match c {
+            '\'' | '"' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth -= 1,
+            ',' | '}' if !quoted && depth == 0 => return Some(rest[..i].trim()),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Splits a `.npy` file into its header and data.
+fn read<'a>(label: &'static str, bytes: &'a [u8]) -> Result<(NpyHeader, &'a [u8]), Error> {
+    if !bytes.starts_with(MAGIC) || bytes.len() < 10 {
+        return Err(error(label, "not a .npy file"));
+    }
+
+    let (header_start, header_len) = match bytes[6] {
+        1 => (10, u16::from_le_bytes([bytes[8], bytes[9]]) as usize),
+        2 | 3 if bytes.len() >= 12 => (12, u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize),
+        version => return Err(error(label, format!(".npy version {} is not supported", version)))
+    };
+
+    let header = bytes
+        .get(header_start..header_start + header_len)
+        .and_then(|header| std::str::from_utf8(header).ok())
+        .ok_or_else(|| error(label, "truncated or invalid .npy header"))?;
+
+    let invalid = || error(label, format!("invalid .npy header {}", header.trim()));
+
+    let descr = dict_value(header, "descr")
+        .and_then(|descr| descr.strip_prefix('\'')?.strip_suffix('\''))
+        .ok_or_else(invalid)?;
+
+    let fortran_order = match dict_value(header, "fortran_order") {
+        Some("True") => true,
+        Some("False") => false,
+        _ => return Err(invalid())
+    };
+
+    let shape = dict_value(header, "shape")
+        .and_then(|shape| shape.strip_prefix('(')?.strip_suffix(')'))
+        .ok_or_else(invalid)?
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse::<usize>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let header = NpyHeader { descr: descr.to_owned(), fortran_order, shape };
+    Ok((header, &bytes[header_start + header_len..]))
+}
+
+/// Checks a header against the element type `T` and returns the number of `T`s in the
+/// data that follows it.
+fn check<T: NpyElement>(label: &'static str, header: &NpyHeader, data: &[u8]) -> Result<usize, Error> {
+    let (order, kind) = header.descr.split_at(1.min(header.descr.len()));
+    let single_byte = kind.ends_with('1');
+
+    if order == ">" && !single_byte {
+        return Err(error(label, format!("big-endian dtype '{}' is not supported, convert it with `astype`", header.descr)));
+    }
+
+    if !matches!(order, "<" | "=" | "|" | ">") || kind != &T::DESCR[1..] {
+        return Err(error(label, format!(
+            "dtype '{}' does not match `{}`, which needs '{}'",
+            header.descr, std::any::type_name::<T>(), T::DESCR
+        )));
+    }
+
+    if header.fortran_order {
+        return Err(error(label, "Fortran-ordered arrays are not supported, save a C-contiguous copy"));
+    }
+
+    let trailing = T::trailing_shape();
+    if !header.shape.ends_with(&trailing) {
+        return Err(error(label, format!(
+            "shape {:?} does not end in {:?} as `{}` needs",
+            header.shape, trailing, std::any::type_name::<T>()
+        )));
+    }
+
+    let scalars: usize = header.shape.iter().product();
+    let scalar_size = std::mem::size_of::<T>() / T::SCALARS.max(1);
+
+    if data.len() < scalars * scalar_size {
+        return Err(error(label, format!("shape {:?} needs {} bytes of data, found {}", header.shape, scalars * scalar_size, data.len())));
+    }
+
+    Ok(scalars / T::SCALARS.max(1))
+}
+
+/// Encodes `data` as a version 1 `.npy` file with the given shape.
+fn write<T: NpyElement>(shape: &[usize], data: &[T]) -> Vec<u8> {
+    let shape = match shape {
+        [dim] => format!("({},)", dim),
+        shape => format!("({})", shape.iter().map(usize::to_string).collect::<Vec<_>>().join(", "))
+    };
+
+    let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", T::DESCR, shape);
+
+    // Pad with spaces and a newline so the data starts 64-byte aligned
+    let unpadded = MAGIC.len() + 4 + header.len() + 1;
+    header.extend(std::iter::repeat_n(' ', unpadded.next_multiple_of(64) - unpadded));
+    header.push('\n');
+
+    let mut bytes = Vec::with_capacity(MAGIC.len() + 4 + header.len() + std::mem::size_of_val(data));
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&[1, 0]);
+    bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    bytes.extend_from_slice(bytemuck::cast_slice(data));
+    bytes
+}
+
+/// The shape to save a buffer of `count` elements with: its recorded element shape if
+/// that still fits, otherwise `count`, followed by the element's trailing dimensions.
+fn shape_for<T: NpyElement>(recorded: Option<&Vec<usize>>, count: usize) -> Vec<usize> {
+    let mut shape = match recorded {
+        Some(shape) if shape.iter().product::<usize>() == count => shape.clone(),
+        _ => vec![count]
+    };
+
+    shape.extend(T::trailing_shape());
+    shape
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, Error> {
+    std::fs::read(path).map_err(|source| Error::Io { path: path.to_owned(), source })
+}
+
+fn write_file(path: &Path, bytes: &[u8]) -> Result<(), Error> {
+    std::fs::write(path, bytes).map_err(|source| Error::Io { path: path.to_owned(), source })
+}
+
+/// Creates a buffer from the bytes of a `.npy` file and records its shape.
+fn create_buffer<P: ComputeProgram + ?Sized, T: NpyElement>(
+    program: &mut P,
+    label: &'static str,
+    bytes: &[u8],
+    usage: wgpu::BufferUsages
+) -> Result<(), Error> {
+    let (header, data) = read(label, bytes)?;
+    let count = check::<T>(label, &header, data)?;
+
+    // `data` is not necessarily aligned for `T`
+    let mut elements = vec![T::zeroed(); count];
+    bytemuck::cast_slice_mut::<T, u8>(&mut elements).copy_from_slice(&data[..count * std::mem::size_of::<T>()]);
+
+    // Record the shape of the elements, without the dimensions inside each element
+    let mut shape = header.shape;
+    shape.truncate(shape.len() - T::trailing_shape().len());
+
+    program.add_buffer_init(label, usage, &elements);
+    program.storage_mut().buffer_shapes.insert(label, shape);
+    Ok(())
+}
+
+/// Reads a buffer back through a staging buffer and encodes it as a `.npy` file.
+fn encode_buffer<P: ComputeProgram + ?Sized, T: NpyElement>(program: &P, label: &'static str) -> Result<Vec<u8>, Error> {
+    let elements = pollster::block_on(program.read_buffer::<T>(label))?;
+    let shape = shape_for::<T>(program.storage().buffer_shapes.get(label), elements.len());
+    Ok(write(&shape, &elements))
+}
+
+pub(crate) fn load<P: ComputeProgram + ?Sized, T: NpyElement>(
+    program: &mut P,
+    label: &'static str,
+    path: &Path,
+    usage: wgpu::BufferUsages
+) -> Result<(), Error> {
+    create_buffer::<P, T>(program, label, &read_file(path)?, usage)
+}
+
+pub(crate) fn save<P: ComputeProgram + ?Sized, T: NpyElement>(program: &P, label: &'static str, path: &Path) -> Result<(), Error> {
+    write_file(path, &encode_buffer::<P, T>(program, label)?)
+}
+
+#[cfg(feature = "npz")]
+fn zip_error(path: &Path, error: zip::result::ZipError) -> Error {
+    match error {
+        zip::result::ZipError::Io(source) => Error::Io { path: path.to_owned(), source },
+        error => Error::Io { path: path.to_owned(), source: std::io::Error::new(std::io::ErrorKind::InvalidData, error) }
+    }
+}
+
+/// Creates a buffer from the array `name` of a `.npz` archive. `name` may be given with
+/// or without the `.npy` extension NumPy adds to every entry.
+#[cfg(feature = "npz")]
+pub(crate) fn load_npz<P: ComputeProgram + ?Sized, T: NpyElement>(
+    program: &mut P,
+    label: &'static str,
+    path: &Path,
+    name: &str,
+    usage: wgpu::BufferUsages
+) -> Result<(), Error> {
+    use std::io::Read;
+
+    let file = std::fs::File::open(path).map_err(|source| Error::Io { path: path.to_owned(), source })?;
+    let mut archive = zip::ZipArchive::new(file).map_err(|error| zip_error(path, error))?;
+
+    let entry = if name.ends_with(".npy") { name.to_owned() } else { format!("{}.npy", name) };
+    let mut file = archive.by_name(&entry).map_err(|zip_err| match zip_err {
+        zip::result::ZipError::FileNotFound => error(label, format!("{} has no array `{}`", path.display(), entry)),
+        zip_err => zip_error(path, zip_err)
+    })?;
+
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes).map_err(|source| Error::Io { path: path.to_owned(), source })?;
+    create_buffer::<P, T>(program, label, &bytes, usage)
+}
+
+/// Saves buffers as the arrays of an uncompressed `.npz` archive, named by their labels.
+#[cfg(feature = "npz")]
+pub(crate) fn save_npz<P: ComputeProgram + ?Sized, T: NpyElement>(program: &P, path: &Path, labels: &[&'static str]) -> Result<(), Error> {
+    use std::io::Write;
+
+    let file = std::fs::File::create(path).map_err(|source| Error::Io { path: path.to_owned(), source })?;
+    let mut archive = zip::ZipWriter::new(file);
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored)
+        .large_file(true);
+
+    for &label in labels {
+        let bytes = encode_buffer::<P, T>(program, label)?;
+
+        archive.start_file(format!("{}.npy", label), options).map_err(|error| zip_error(path, error))?;
+        archive.write_all(&bytes).map_err(|source| Error::Io { path: path.to_owned(), source })?;
+    }
+
+    archive.finish().map_err(|error| zip_error(path, error))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A version 1 `.npy` file with a raw header dict followed by `data_len` zero bytes.
+    fn file(dict: &str, data_len: usize) -> Vec<u8> {
+        let header = format!("{}\n", dict);
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[1, 0]);
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.resize(bytes.len() + data_len, 0);
+        bytes
+    }
+
+    fn array(descr: &str, shape: &str, data_len: usize) -> Vec<u8> {
+        file(&format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", descr, shape), data_len)
+    }
+
+    fn message<T: std::fmt::Debug>(result: Result<T, Error>) -> String {
+        match result {
+            Err(Error::Npy { message, .. }) => message,
+            other => panic!("expected an npy error, got {:?}", other)
+        }
+    }
+
+    fn check_file<T: NpyElement>(bytes: &[u8]) -> Result<usize, Error> {
+        let (header, data) = read("test", bytes)?;
+        check::<T>("test", &header, data)
+    }
+
+    #[test]
+    fn header_round_trip() {
+        for shape in [vec![0], vec![1], vec![3], vec![2, 3], vec![4, 5, 6], vec![123456789]] {
+            let data: Vec<f32> = (0..shape.iter().product::<usize>().min(64)).map(|i| i as f32).collect();
+            let bytes = write(&shape, &data);
+            let data_start = bytes.len() - std::mem::size_of_val(&data[..]);
+
+            // The header is padded so the data starts 64-byte aligned, ending in a newline
+            assert_eq!(data_start % 64, 0);
+            assert_eq!(bytes[data_start - 1], b'\n');
+
+            let (header, rest) = read("test", &bytes).unwrap();
+            assert_eq!(header.descr, "<f4");
+            assert!(!header.fortran_order);
+            assert_eq!(header.shape, shape);
+            assert_eq!(rest, bytemuck::cast_slice::<f32, u8>(&data));
+        }
+    }
+
+    #[test]
+    fn array_elements_add_trailing_dimensions() {
+        let data = [[1u8, 2, 3], [4, 5, 6]];
+        let shape = shape_for::<[u8; 3]>(None, data.len());
+        assert_eq!(shape, [2, 3]);
+
+        let bytes = write(&shape, &data);
+        assert_eq!(check_file::<[u8; 3]>(&bytes).unwrap(), 2);
+        assert_eq!(check_file::<u8>(&bytes).unwrap(), 6);
+
+        // The recorded shape is kept only while it still matches the element count
+        assert_eq!(shape_for::<[[f32; 2]; 3]>(Some(&vec![4, 5]), 20), [4, 5, 3, 2]);
+        assert_eq!(shape_for::<[f32; 4]>(Some(&vec![4, 5]), 7), [7, 4]);
+
+        let message = message(check_file::<[f32; 2]>(&array("<f4", "(2, 3)", 24)));
+        assert!(message.contains("does not end in [2]"), "{}", message);
+    }
+
+    #[test]
+    fn dtype_mismatch() {
+        let bytes = array("<f4", "(4,)", 16);
+        assert_eq!(check_file::<f32>(&bytes).unwrap(), 4);
+        assert!(message(check_file::<u32>(&bytes)).contains("does not match"));
+        assert!(message(check_file::<f64>(&bytes)).contains("does not match"));
+        assert!(message(check_file::<i8>(&array("|u1", "(4,)", 4))).contains("does not match"));
+
+        assert!(message(check_file::<f32>(&array(">f4", "(4,)", 16))).contains("big-endian"));
+
+        // Byte order does not matter for single bytes
+        assert_eq!(check_file::<u8>(&array(">u1", "(4,)", 4)).unwrap(), 4);
+        assert_eq!(check_file::<u8>(&array("|u1", "(4,)", 4)).unwrap(), 4);
+
+        assert!(message(check_file::<f32>(&array("<f4", "(4,)", 15))).contains("needs 16 bytes"));
+    }
+
+    #[test]
+    fn malformed_headers() {
+        assert!(message(read("test", b"not a npy file")).contains("not a .npy file"));
+        assert!(message(read("test", &MAGIC[..4])).contains("not a .npy file"));
+
+        let mut bytes = array("<f4", "(4,)", 0);
+        bytes[6] = 9;
+        assert!(message(read("test", &bytes)).contains("version 9"));
+
+        let bytes = array("<f4", "(4,)", 0);
+        assert!(message(read("test", &bytes[..bytes.len() - 5])).contains("truncated"));
+
+        for shape in ["4", "(4", "(four,)", "(-1,)"] {
+            assert!(message(read("test", &array("<f4", shape, 0))).contains("invalid .npy header"), "{}", shape);
+        }
+
+        for dict in [
+            "{'descr': '<f4', 'shape': (4,), }",
+            "{'descr': <f4, 'fortran_order': False, 'shape': (4,), }",
+            "{'descr': '<f4', 'fortran_order': Maybe, 'shape': (4,), }",
+            "{'fortran_order': False, 'shape': (4,), }"
+        ] {
+            assert!(message(read("test", &file(dict, 0))).contains("invalid .npy header"), "{}", dict);
+        }
+    }
+
+    #[test]
+    fn fortran_order_is_rejected() {
+        let bytes = file("{'descr': '<f4', 'fortran_order': True, 'shape': (2, 2), }", 16);
+        assert!(message(check_file::<f32>(&bytes)).contains("Fortran"));
+    }
+}
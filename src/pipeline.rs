@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+
+use crate::{Error, Storage};
+
+/// Everything `add_compute_pipelines` was given for one kernel, kept so the pipeline
+/// can be rebuilt when its module is reloaded.
+#[derive(Clone)]
+pub struct ComputePipelineParams {
+    pub module: &'static str,
+    pub bind_groups: Vec<&'static str>,
+    pub entry_point: &'static str,
+    pub push_constant_ranges: Vec<wgpu::PushConstantRange>,
+    pub constants: HashMap<String, f64>,
+    pub zero_initialize_workgroup_memory: bool
+}
+
+/// An owned `wgpu::VertexBufferLayout`.
+#[derive(Clone)]
+pub struct VertexBufferLayoutParams {
+    pub array_stride: wgpu::BufferAddress,
+    pub step_mode: wgpu::VertexStepMode,
+    pub attributes: Vec<wgpu::VertexAttribute>
+}
+
+/// Everything `add_render_pipelines` was given for one kernel, kept so the pipeline
+/// can be rebuilt when its module is reloaded.
+#[derive(Clone)]
+pub struct RenderPipelineParams {
+    pub module: &'static str,
+    pub bind_groups: Vec<&'static str>,
+    pub vertex: &'static str,
+    pub fragment: &'static str,
+    pub push_constant_ranges: Vec<wgpu::PushConstantRange>,
+    pub targets: Vec<Option<wgpu::ColorTargetState>>,
+    pub vertex_buffer_layouts: Vec<VertexBufferLayoutParams>,
+    pub vertex_constants: HashMap<String, f64>,
+    pub vertex_zero_initialize_workgroup_memory: bool,
+    pub fragment_constants: HashMap<String, f64>,
+    pub fragment_zero_initialize_workgroup_memory: bool
+}
+
+fn create_pipeline_layout(
+    device: &wgpu::Device,
+    storage: &Storage,
+    bind_groups: &[&'static str],
+    push_constant_ranges: &[wgpu::PushConstantRange]
+) -> wgpu::PipelineLayout {
+    let bind_group_layouts: Vec<_> = bind_groups
+        .iter()
+        .map(|x| &storage.bind_group_layouts[x])
+        .collect();
+
+    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: None,
+        bind_group_layouts: &bind_group_layouts,
+        push_constant_ranges
+    })
+}
+
+pub(crate) fn create_compute_pipeline(
+    device: &wgpu::Device,
+    storage: &Storage,
+    module: &wgpu::ShaderModule,
+    params: &ComputePipelineParams
+) -> wgpu::ComputePipeline {
+    let pipeline_layout = create_pipeline_layout(device, storage, &params.bind_groups, &params.push_constant_ranges);
+
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: None,
+        layout: Some(&pipeline_layout),
+        module,
+        entry_point: params.entry_point,
+        compilation_options: wgpu::PipelineCompilationOptions {
+            constants: &params.constants,
+            zero_initialize_workgroup_memory: params.zero_initialize_workgroup_memory
+        }
+    })
+}
+
+pub(crate) fn create_render_pipeline(
+    device: &wgpu::Device,
+    storage: &Storage,
+    module: &wgpu::ShaderModule,
+    params: &RenderPipelineParams
+) -> wgpu::RenderPipeline {
+    let pipeline_layout = create_pipeline_layout(device, storage, &params.bind_groups, &params.push_constant_ranges);
+
+    let vertex_buffer_layouts: Vec<_> = params.vertex_buffer_layouts
+        .iter()
+        .map(|layout| wgpu::VertexBufferLayout {
+            array_stride: layout.array_stride,
+            step_mode: layout.step_mode,
+            attributes: &layout.attributes
+        })
+        .collect();
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: None,
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: params.vertex,
+            buffers: &vertex_buffer_layouts,
+            compilation_options: wgpu::PipelineCompilationOptions {
+                constants: &params.vertex_constants,
+                zero_initialize_workgroup_memory: params.vertex_zero_initialize_workgroup_memory
+            }
+        },
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point: params.fragment,
+            targets: &params.targets,
+            compilation_options: wgpu::PipelineCompilationOptions {
+                constants: &params.fragment_constants,
+                zero_initialize_workgroup_memory: params.fragment_zero_initialize_workgroup_memory
+            }
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+/// Returns the `@workgroup_size` of a compute entry point.
+pub(crate) fn workgroup_size(module: &naga::Module, entry_point: &str) -> Option<[u32; 3]> {
+    module.entry_points
+        .iter()
+        .find(|e| e.stage == naga::ShaderStage::Compute && e.name == entry_point)
+        .map(|e| e.workgroup_size)
+}
+
+/// Checks pipeline-overridable constants against the `override` declarations of `module`:
+/// every key must name an override (by name or `@id`), every override without a default
+/// needs a value, and values must be representable in the override's type.
+///
+/// Overrides cannot size workgroups, since naga keeps `@workgroup_size` as plain numbers,
+/// and modules using one there already fail to parse.
+pub(crate) fn check_constants(label: &'static str, module: &naga::Module, constants: &HashMap<String, f64>) -> Result<(), Error> {
+    let error = |message: String| Error::Pipeline { label, message };
+
+    for name in constants.keys() {
+        let declared = module.overrides.iter().any(|(_, o)| {
+            o.name.as_deref() == Some(name.as_str()) || o.id.map(|id| id.to_string()).as_deref() == Some(name.as_str())
+        });
+
+        // Sizes that cannot be overrides, like `@workgroup_size`, are often `const`s instead
+        if !declared && module.constants.iter().any(|(_, c)| c.name.as_deref() == Some(name.as_str())) {
+            return Err(error(format!("`{}` is a `const`, not an `override`, so it cannot be set per pipeline", name)));
+        }
+
+        if !declared {
+            return Err(error(format!("`{}` is not an override declared by the module", name)));
+        }
+    }
+
+    for (_, o) in module.overrides.iter() {
+        let value = constants
+            .get(o.name.as_deref().unwrap_or_default())
+            .or_else(|| o.id.and_then(|id| constants.get(&id.to_string())));
+
+        let name = o.name.clone().or_else(|| o.id.map(|id| id.to_string())).unwrap_or_default();
+
+        let Some(&value) = value else {
+            if o.init.is_none() {
+                return Err(error(format!("override `{}` has no default and no value was given", name)));
+            }
+            continue;
+        };
+
+        let representable = match module.types[o.ty].inner.scalar_kind() {
+            Some(naga::ScalarKind::Bool) => true,
+            Some(naga::ScalarKind::Uint) => value.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(&value),
+            Some(naga::ScalarKind::Sint) => value.fract() == 0.0 && (i32::MIN as f64..=i32::MAX as f64).contains(&value),
+            Some(naga::ScalarKind::Float) => value.is_finite() && value.abs() <= f32::MAX as f64,
+            _ => true
+        };
+
+        if !representable {
+            return Err(error(format!("{} is not a valid value for override `{}`", value, name)));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,522 @@
+use std::{collections::{HashMap, HashSet}, ops::Range, path::{Path, PathBuf}};
+
+use crate::Error;
+
+/// A small WGSL preprocessor.
+///
+/// Supported directives, each on its own line:
+/// - `#include "file.wgsl"` pastes another file. Every file is included at most once.
+/// - `#define NAME` and `#define NAME value`, `#undef NAME`
+/// - `#ifdef NAME`, `#ifndef NAME`, `#else`, `#endif`
+///
+/// Defined names with a value are substituted wherever they appear as a whole identifier
+/// outside of comments.
+/// Included files are looked up in the virtual files first, then next to the including
+/// file, then in each search path in order.
+#[derive(Default, Clone)]
+pub struct Preprocessor {
+    search_paths: Vec<PathBuf>,
+    virtual_files: HashMap<String, String>,
+    defines: HashMap<String, String>
+}
+
+/// A file that contributed lines to a [`PreprocessedSource`].
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub name: String,
+    /// `None` for virtual files.
+    pub path: Option<PathBuf>,
+    pub source: String
+}
+
+/// The output of the preprocessor, along with a map from output lines to original lines.
+#[derive(Debug, Clone)]
+pub struct PreprocessedSource {
+    pub source: String,
+    pub files: Vec<SourceFile>,
+    /// `(file index, 1-based line)` for every line of `source`
+    lines: Vec<(usize, u32)>
+}
+
+impl PreprocessedSource {
+    /// Maps a 1-based line of the preprocessed source back to its original file and line.
+    pub fn original_location(&self, line: u32) -> Option<(&SourceFile, u32)> {
+        let (file, line) = *self.lines.get(line.checked_sub(1)? as usize)?;
+        Some((&self.files[file], line))
+    }
+
+    /// Maps a byte range of the preprocessed source to a file index and a byte range of
+    /// that file. Ranges spanning several lines are cut at the end of the first line.
+    pub fn original_range(&self, range: Range<usize>) -> Option<(usize, Range<usize>)> {
+        let before = self.source.get(..range.start)?;
+        let output_line = before.matches('\n').count();
+        let column = range.start - before.rfind('\n').map_or(0, |i| i + 1);
+
+        let (file, line) = *self.lines.get(output_line)?;
+        let source = &self.files[file].source;
+
+        let line_start: usize = source.split_inclusive('\n').take(line as usize - 1).map(str::len).sum();
+        let line_length = source[line_start..].lines().next().map_or(0, str::len);
+
+        // Substituted defines shift columns, so the range may no longer fall on char boundaries
+        let floor = |mut i: usize| { while !source.is_char_boundary(i) { i -= 1; } i };
+        let start = floor(line_start + column.min(line_length));
+        let end = floor((start + range.len()).min(line_start + line_length)).max(start);
+        Some((file, start..end))
+    }
+}
+
+struct Conditional {
+    active: bool,
+    parent_active: bool,
+    seen_else: bool
+}
+
+struct State<'a> {
+    preprocessor: &'a Preprocessor,
+    defines: HashMap<String, String>,
+    included: HashSet<String>,
+    files: Vec<SourceFile>,
+    output: String,
+    lines: Vec<(usize, u32)>
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a directory to look up `#include`d files in.
+    pub fn add_search_path(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.search_paths.push(path.into());
+        self
+    }
+
+    /// Registers an in-memory file which takes priority over files on disk.
+    pub fn add_virtual_file(&mut self, name: impl Into<String>, source: impl Into<String>) -> &mut Self {
+        self.virtual_files.insert(name.into(), source.into());
+        self
+    }
+
+    /// Predefines `name`, as if every processed file started with `#define name value`.
+    pub fn define(&mut self, name: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.defines.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn undefine(&mut self, name: &str) -> &mut Self {
+        self.defines.remove(name);
+        self
+    }
+
+    /// Processes a virtual file, or a file on disk resolved against the search paths.
+    pub fn process(&self, name: &str) -> Result<PreprocessedSource, Error> {
+        let (key, path, source) = self.resolve(name, None)?;
+        self.run(key, name.to_string(), path, source)
+    }
+
+    /// Processes `source` as if it were a file called `name`.
+    pub fn process_str(&self, name: &str, source: &str) -> Result<PreprocessedSource, Error> {
+        self.run(name.to_string(), name.to_string(), None, source.to_string())
+    }
+
+    fn run(&self, key: String, name: String, path: Option<PathBuf>, source: String) -> Result<PreprocessedSource, Error> {
+        let mut state = State {
+            preprocessor: self,
+            defines: self.defines.clone(),
+            included: HashSet::new(),
+            files: Vec::new(),
+            output: String::new(),
+            lines: Vec::new()
+        };
+
+        state.included.insert(key);
+        state.process_file(name, path, source)?;
+
+        Ok(PreprocessedSource {
+            source: state.output,
+            files: state.files,
+            lines: state.lines
+        })
+    }
+
+    /// Returns a key identifying the file for include-once purposes, its path and its contents.
+    fn resolve(&self, name: &str, includer: Option<&Path>) -> Result<(String, Option<PathBuf>, String), Error> {
+        if let Some(source) = self.virtual_files.get(name) {
+            return Ok((name.to_string(), None, source.clone()));
+        }
+
+        let candidates = includer
+            .and_then(Path::parent)
+            .into_iter()
+            .chain(self.search_paths.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(name))
+            .chain(std::iter::once(PathBuf::from(name)));
+
+        for candidate in candidates {
+            if candidate.is_file() {
+                let source = std::fs::read_to_string(&candidate)
+                    .map_err(|source| Error::Io { path: candidate.clone(), source })?;
+                let key = candidate.canonicalize().unwrap_or_else(|_| candidate.clone());
+                return Ok((key.to_string_lossy().into_owned(), Some(candidate), source));
+            }
+        }
+
+        Err(Error::Io {
+            path: PathBuf::from(name),
+            source: std::io::Error::new(std::io::ErrorKind::NotFound, "file not found in virtual files or search paths")
+        })
+    }
+}
+
+impl State<'_> {
+    fn process_file(&mut self, name: String, path: Option<PathBuf>, source: String) -> Result<(), Error> {
+        let file = self.files.len();
+        self.files.push(SourceFile { name: name.clone(), path: path.clone(), source: source.clone() });
+
+        let error = |line: usize, message: String| Error::Preprocessor {
+            file: name.clone(),
+            line: line as u32 + 1,
+            message
+        };
+
+        let mut conditionals: Vec<Conditional> = Vec::new();
+        let mut comment_depth = 0;
+
+        for (i, line) in source.lines().enumerate() {
+            let active = conditionals.last().is_none_or(|c| c.active);
+            let trimmed = line.trim_start();
+
+            let Some(directive) = trimmed.strip_prefix('#') else {
+                if active {
+                    let substituted = self.substitute(line, &mut comment_depth);
+                    self.output.push_str(&substituted);
+                    self.output.push('\n');
+                    self.lines.push((file, i as u32 + 1));
+                }
+                continue;
+            };
+
+            let directive = directive.split("//").next().unwrap().trim();
+            let (keyword, argument) = directive
+                .split_once(char::is_whitespace)
+                .map(|(k, a)| (k, a.trim()))
+                .unwrap_or((directive, ""));
+
+            match keyword {
+                "ifdef" | "ifndef" => {
+                    let name = parse_identifier(argument).ok_or_else(|| error(i, format!("expected a name after #{}", keyword)))?;
+                    let defined = self.defines.contains_key(name);
+                    conditionals.push(Conditional {
+                        active: active && (defined == (keyword == "ifdef")),
+                        parent_active: active,
+                        seen_else: false
+                    });
+                },
+                "else" => {
+                    let conditional = conditionals.last_mut().ok_or_else(|| error(i, "#else without #ifdef".into()))?;
+                    if conditional.seen_else {
+                        return Err(error(i, "duplicate #else".into()));
+                    }
+                    conditional.seen_else = true;
+                    conditional.active = conditional.parent_active && !conditional.active;
+                },
+                "endif" => {
+                    conditionals.pop().ok_or_else(|| error(i, "#endif without #ifdef".into()))?;
+                },
+                _ if !active => {},
+                "define" => {
+                    let (name, value) = argument
+                        .split_once(char::is_whitespace)
+                        .map(|(n, v)| (n, v.trim()))
+                        .unwrap_or((argument, ""));
+                    let name = parse_identifier(name).ok_or_else(|| error(i, "expected a name after #define".into()))?;
+                    let value = self.substitute(value, &mut 0);
+                    self.defines.insert(name.to_string(), value);
+                },
+                "undef" => {
+                    let name = parse_identifier(argument).ok_or_else(|| error(i, "expected a name after #undef".into()))?;
+                    self.defines.remove(name);
+                },
+                "include" => {
+                    let include = argument
+                        .strip_prefix('"')
+                        .and_then(|a| a.strip_suffix('"'))
+                        .ok_or_else(|| error(i, "expected a quoted file name after #include".into()))?;
+
+                    let (key, include_path, include_source) = self.preprocessor
+                        .resolve(include, path.as_deref())
+                        .map_err(|e| error(i, format!("cannot include \"{}\": {}", include, e)))?;
+
+                    if self.included.insert(key) {
+                        self.process_file(include.to_string(), include_path, include_source)?;
+                    }
+                },
+                _ => return Err(error(i, format!("unknown directive #{}", keyword)))
+            }
+        }
+
+        if !conditionals.is_empty() {
+            return Err(error(source.lines().count().saturating_sub(1), "unterminated #ifdef".into()));
+        }
+
+        Ok(())
+    }
+
+    /// Replaces every identifier that has a defined value, leaving comments untouched.
+    /// `comment_depth` carries the nesting of block comments over from the previous line.
+    fn substitute(&self, line: &str, comment_depth: &mut usize) -> String {
+        let mut result = String::with_capacity(line.len());
+        let mut rest = line;
+
+        loop {
+            if *comment_depth > 0 {
+                let Some((index, token)) = find_first(rest, &["/*", "*/"]) else {
+                    result.push_str(rest);
+                    break;
+                };
+                result.push_str(&rest[..index + 2]);
+                if token == "/*" { *comment_depth += 1; } else { *comment_depth -= 1; }
+                rest = &rest[index + 2..];
+                continue;
+            }
+
+            match find_first(rest, &["//", "/*"]) {
+                Some((index, "/*")) => {
+                    self.substitute_code(&rest[..index], &mut result);
+                    result.push_str("/*");
+                    *comment_depth += 1;
+                    rest = &rest[index + 2..];
+                },
+                Some((index, _)) => {
+                    self.substitute_code(&rest[..index], &mut result);
+                    result.push_str(&rest[index..]);
+                    break;
+                },
+                None => {
+                    self.substitute_code(rest, &mut result);
+                    break;
+                }
+            }
+        }
+
+        result
+    }
+
+    fn substitute_code(&self, code: &str, result: &mut String) {
+        let mut rest = code;
+
+        while let Some(start) = rest.find(is_identifier_start) {
+            let (before, from_start) = rest.split_at(start);
+            let end = from_start.find(|c: char| !is_identifier_continue(c)).unwrap_or(from_start.len());
+            let (identifier, after) = from_start.split_at(end);
+
+            result.push_str(before);
+
+            // Digits preceding the identifier mean it is a literal suffix such as `1u`
+            let is_suffix = before.chars().last().is_some_and(is_identifier_continue);
+            match self.defines.get(identifier) {
+                Some(value) if !value.is_empty() && !is_suffix => result.push_str(value),
+                _ => result.push_str(identifier)
+            }
+
+            rest = after;
+        }
+
+        result.push_str(rest);
+    }
+}
+
+/// Returns the position of whichever token occurs first in `text`, and that token.
+fn find_first<'t>(text: &str, tokens: &[&'t str]) -> Option<(usize, &'t str)> {
+    tokens.iter().filter_map(|&t| text.find(t).map(|i| (i, t))).min_by_key(|&(i, _)| i)
+}
+
+fn is_identifier_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_identifier_continue(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn parse_identifier(text: &str) -> Option<&str> {
+    let mut chars = text.chars();
+    let valid = chars.next().is_some_and(is_identifier_start) && chars.all(is_identifier_continue);
+    valid.then_some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preprocessor(files: &[(&str, &str)]) -> Preprocessor {
+        let mut preprocessor = Preprocessor::new();
+        for (name, source) in files {
+            preprocessor.add_virtual_file(*name, *source);
+        }
+        preprocessor
+    }
+
+    fn process(files: &[(&str, &str)]) -> String {
+        preprocessor(files).process(files[0].0).unwrap().source
+    }
+
+    fn error_line(result: Result<PreprocessedSource, Error>) -> (String, u32, String) {
+        match result {
+            Err(Error::Preprocessor { file, line, message }) => (file, line, message),
+            other => panic!("expected a preprocessor error, got {:?}", other.map(|s| s.source))
+        }
+    }
+
+    #[test]
+    fn includes_each_file_once() {
+        let source = process(&[
+            ("main.wgsl", "#include \"a.wgsl\"\n#include \"b.wgsl\"\nmain"),
+            ("a.wgsl", "#include \"common.wgsl\"\na"),
+            ("b.wgsl", "#include \"common.wgsl\"\nb"),
+            ("common.wgsl", "common")
+        ]);
+        assert_eq!(source, "common\na\nb\nmain\n");
+    }
+
+    #[test]
+    fn include_cycles_terminate() {
+        let source = process(&[
+            ("main.wgsl", "#include \"a.wgsl\"\nmain"),
+            ("a.wgsl", "#include \"b.wgsl\"\na"),
+            ("b.wgsl", "#include \"a.wgsl\"\n#include \"main.wgsl\"\nb")
+        ]);
+        assert_eq!(source, "b\na\nmain\n");
+    }
+
+    #[test]
+    fn missing_include_reports_the_including_line() {
+        let (file, line, message) = error_line(preprocessor(&[("main.wgsl", "x\n#include \"missing.wgsl\"")]).process("main.wgsl"));
+        assert_eq!((file.as_str(), line), ("main.wgsl", 2));
+        assert!(message.contains("missing.wgsl"), "{}", message);
+    }
+
+    #[test]
+    fn nested_conditionals() {
+        let source = "\
+#ifdef A
+a
+#ifndef B
+not_b
+#else
+b
+#endif
+#else
+not_a
+#ifdef B
+unreachable
+#endif
+#endif";
+
+        let mut preprocessor = preprocessor(&[("main.wgsl", source)]);
+        assert_eq!(preprocessor.process("main.wgsl").unwrap().source, "not_a\n");
+
+        preprocessor.define("A", "");
+        assert_eq!(preprocessor.process("main.wgsl").unwrap().source, "a\nnot_b\n");
+
+        preprocessor.define("B", "");
+        assert_eq!(preprocessor.process("main.wgsl").unwrap().source, "a\nb\n");
+    }
+
+    #[test]
+    fn inactive_branches_ignore_defines() {
+        let source = process(&[("main.wgsl", "#ifdef A\n#define B\n#endif\n#ifdef B\nb\n#endif\nend")]);
+        assert_eq!(source, "end\n");
+    }
+
+    #[test]
+    fn unbalanced_conditionals_are_errors() {
+        let (_, line, message) = error_line(Preprocessor::new().process_str("main.wgsl", "a\n#endif"));
+        assert_eq!(line, 2);
+        assert!(message.contains("#endif"), "{}", message);
+
+        let (_, line, _) = error_line(Preprocessor::new().process_str("main.wgsl", "#ifdef A\n#else\n#else\n#endif"));
+        assert_eq!(line, 3);
+
+        let (_, _, message) = error_line(Preprocessor::new().process_str("main.wgsl", "#ifdef A\na"));
+        assert!(message.contains("unterminated"), "{}", message);
+    }
+
+    #[test]
+    fn defines_substitute_whole_identifiers() {
+        let source = process(&[("main.wgsl", "#define N 64\n#define SIZE N * 2u\nlet a = N + SIZE + N_2 + 1N;")]);
+        assert_eq!(source, "let a = 64 + 64 * 2u + N_2 + 1N;\n");
+    }
+
+    #[test]
+    fn defines_skip_literal_suffixes() {
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.define("u", "x").define("f", "y");
+        let source = preprocessor.process_str("main.wgsl", "let a = 1u + 2.5f + u + f;").unwrap().source;
+        assert_eq!(source, "let a = 1u + 2.5f + x + y;\n");
+    }
+
+    #[test]
+    fn defines_skip_comments() {
+        let source = "\
+#define N 4
+N // N
+N /* N */ N
+/* N
+   /* nested N */ N
+*/ N";
+        assert_eq!(process(&[("main.wgsl", source)]), "\
+4 // N
+4 /* N */ 4
+/* N
+   /* nested N */ N
+*/ 4
+");
+    }
+
+    #[test]
+    fn undef_removes_definition() {
+        let source = process(&[("main.wgsl", "#define N 4\nN\n#undef N\nN\n#ifdef N\nn\n#endif")]);
+        assert_eq!(source, "4\nN\n");
+    }
+
+    #[test]
+    fn maps_lines_across_includes() {
+        let processed = preprocessor(&[
+            ("main.wgsl", "#include \"a.wgsl\"\n\nmain\n#include \"b.wgsl\"\nend"),
+            ("a.wgsl", "#ifdef X\nx\n#endif\na"),
+            ("b.wgsl", "b1\nb2")
+        ]).process("main.wgsl").unwrap();
+
+        assert_eq!(processed.source, "a\n\nmain\nb1\nb2\nend\n");
+
+        let location = |line| processed.original_location(line).map(|(file, line)| (file.name.as_str(), line));
+        assert_eq!(location(1), Some(("a.wgsl", 4)));
+        assert_eq!(location(2), Some(("main.wgsl", 2)));
+        assert_eq!(location(3), Some(("main.wgsl", 3)));
+        assert_eq!(location(4), Some(("b.wgsl", 1)));
+        assert_eq!(location(5), Some(("b.wgsl", 2)));
+        assert_eq!(location(6), Some(("main.wgsl", 5)));
+        assert_eq!(location(0), None);
+        assert_eq!(location(7), None);
+    }
+
+    #[test]
+    fn maps_ranges_across_includes() {
+        let processed = preprocessor(&[
+            ("main.wgsl", "// header\n#include \"a.wgsl\"\nlet main = 1;"),
+            ("a.wgsl", "\nlet a = 2;")
+        ]).process("main.wgsl").unwrap();
+
+        let start = processed.source.find("a = 2").unwrap();
+        let (file, range) = processed.original_range(start..start + 5).unwrap();
+        assert_eq!(processed.files[file].name, "a.wgsl");
+        assert_eq!(&processed.files[file].source[range], "a = 2");
+
+        let start = processed.source.find("main").unwrap();
+        let (file, range) = processed.original_range(start..start + 100).unwrap();
+        assert_eq!(processed.files[file].name, "main.wgsl");
+        assert_eq!(&processed.files[file].source[range], "main = 1;");
+    }
+}
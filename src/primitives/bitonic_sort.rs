@@ -0,0 +1,131 @@
+use crate::{labels, BindGroupItem, ComputeKernel, ComputeProgram, Error, Preprocessor};
+
+const WORKGROUP_SIZE: u32 = 256;
+/// The number of elements sorted in workgroup memory at once
+const TILE_SIZE: u32 = WORKGROUP_SIZE * 2;
+
+/// The elements and order of `ComputeProgram::add_bitonic_sort`.
+#[derive(Clone, Copy, Debug)]
+pub struct BitonicSort {
+    /// The WGSL type of the elements, e.g. `f32` or a struct declared in `source`
+    pub element: &'static str,
+    /// WGSL declaring `fn less(a: T, b: T) -> bool`, which must be a strict weak order,
+    /// along with the element struct if there is one
+    pub source: &'static str
+}
+
+impl BitonicSort {
+    /// `f32`s in ascending order
+    pub const F32: Self = Self {
+        element: "f32",
+        source: "fn less(a: f32, b: f32) -> bool { return a < b; }"
+    };
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Step {
+    kernel: &'static str,
+    bind_group: &'static str,
+    invocations: u32
+}
+
+/// The dispatches `ComputeProgram::bitonic_sort` records for one sort.
+#[derive(Clone, Debug)]
+pub struct BitonicSortPasses {
+    steps: Vec<Step>
+}
+
+/// The array stride of the `data` binding, as laid out by naga.
+fn element_stride(module: &naga::Module) -> Option<u32> {
+    let data = module.global_variables.iter().find(|(_, variable)| variable.name.as_deref() == Some("data"))?.1;
+
+    match module.types[data.ty].inner {
+        naga::TypeInner::Array { stride, .. } => Some(stride),
+        _ => None
+    }
+}
+
+/// Compiles the kernels of a sort of `buffer` and plans its steps, with the parameters
+/// and bind groups of each created under labels derived from `label`.
+pub(crate) fn create<P: ComputeProgram + ?Sized>(
+    program: &mut P,
+    label: &'static str,
+    buffer: &'static str,
+    sort: BitonicSort
+) -> Result<(), Error> {
+    let module = labels::derived(label, "sort");
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .add_virtual_file("bitonic_sort.wgsl", include_str!("bitonic_sort.wgsl"))
+        .add_virtual_file("compare.wgsl", sort.source)
+        .define("ELEMENT", sort.element);
+
+    super::add_module(program, module, &preprocessor, "bitonic_sort.wgsl")?;
+
+    let stride = element_stride(&program.storage().naga_modules[module]).unwrap_or(4) as usize;
+    let count = match program.storage().buffer_elements.get(buffer) {
+        Some(elements) if elements.size != stride => {
+            return Err(Error::ElementType { label: buffer, expected: elements.type_name, found: sort.element });
+        },
+        Some(elements) => elements.count,
+        None => program.storage().buffers[buffer].size() as usize / stride
+    } as u32;
+
+    let padded = count.next_power_of_two();
+    let tiles = count.div_ceil(TILE_SIZE).max(1);
+
+    let add_step = |program: &mut P, i: usize, half_size: u32| {
+        let params = labels::derived(label, &format!("params{}", i));
+        let bind_group = labels::derived(label, &format!("step{}", i));
+
+        program.add_buffer_init(params, wgpu::BufferUsages::UNIFORM, &[count, half_size, 0, 0]);
+        program.add_bind_group(bind_group, &[
+            BindGroupItem::StorageBuffer { label: buffer, min_binding_size: stride as u64, read_only: false },
+            BindGroupItem::UniformBuffer { label: params, min_binding_size: 8 }
+        ]);
+
+        bind_group
+    };
+
+    let kernel = |name| labels::derived(label, name);
+    let local = add_step(program, 0, 0);
+    let mut steps = vec![Step { kernel: kernel("local_sort"), bind_group: local, invocations: tiles * WORKGROUP_SIZE }];
+
+    // Blocks larger than a tile are merged by a flip, then dispersed across tiles until
+    // the pairs fit in one tile again
+    let mut size = TILE_SIZE * 2;
+    while size <= padded {
+        let bind_group = add_step(program, steps.len(), size / 2);
+        steps.push(Step { kernel: kernel("flip"), bind_group, invocations: padded / 2 });
+
+        let mut half_size = size / 4;
+        while half_size >= TILE_SIZE {
+            let bind_group = add_step(program, steps.len(), half_size);
+            steps.push(Step { kernel: kernel("disperse"), bind_group, invocations: padded / 2 });
+            half_size /= 2;
+        }
+
+        steps.push(Step { kernel: kernel("local_disperse"), bind_group: local, invocations: tiles * WORKGROUP_SIZE });
+        size *= 2;
+    }
+
+    let kernels = ["local_sort", "local_disperse", "flip", "disperse"].map(|entry_point| ComputeKernel {
+        label: kernel(entry_point),
+        entry_point,
+        constants: &[]
+    });
+    program.add_compute_pipelines(module, &[local], &kernels, &[], None)?;
+
+    program.storage_mut().bitonic_sorts.insert(label, BitonicSortPasses { steps });
+    Ok(())
+}
+
+/// Records every step of the bitonic sort `label`.
+pub(crate) fn record<P: ComputeProgram + ?Sized>(program: &P, encoder: &mut wgpu::CommandEncoder, label: &'static str) -> Result<(), Error> {
+    for step in &program.storage().bitonic_sorts[label].steps {
+        program.dispatch(encoder, step.kernel, &[step.bind_group], step.invocations)?;
+    }
+
+    Ok(())
+}
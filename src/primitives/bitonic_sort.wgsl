@@ -0,0 +1,150 @@
+// Kernels behind `ComputeProgram::add_bitonic_sort`, using the flip and disperse form
+// of bitonic sort, which sorts everything in the same direction. Arrays that are not a
+// power of two long are sorted as if padded with elements greater than all others:
+// pairs whose second index is past the end are never swapped.
+//
+// Stages whose pairs fall within a tile of `TILE_SIZE` elements run in workgroup
+// memory; `local_sort` runs every stage up to blocks of one tile, and `local_disperse`
+// finishes each larger block once its pairs fit in a tile again.
+//
+// Defines set by the host:
+// - `ELEMENT`: the element type, declared in `compare.wgsl` if it is a struct
+#include "compare.wgsl"
+
+const WORKGROUP_SIZE: u32 = 256u;
+const TILE_SIZE: u32 = WORKGROUP_SIZE * 2u;
+
+struct Params {
+    count: u32,
+    // Half the size of the blocks compared by `flip` and `disperse`
+    half_size: u32
+}
+
+@group(0) @binding(0)
+var<storage, read_write> data: array<ELEMENT>;
+
+@group(0) @binding(1)
+var<uniform> params: Params;
+
+var<workgroup> tile: array<ELEMENT, TILE_SIZE>;
+
+// Large dispatches are folded into the y and z dimensions
+fn workgroup_index(workgroup_id: vec3u, num_workgroups: vec3u) -> u32 {
+    return workgroup_id.x + (workgroup_id.y + workgroup_id.z * num_workgroups.y) * num_workgroups.x;
+}
+
+// Pair `t` of a flip over blocks of `2 * h`: the first half against the mirrored second
+fn flip_pair(t: u32, h: u32) -> vec2u {
+    let start = (t / h) * 2u * h;
+    let offset = t % h;
+    return vec2u(start + offset, start + 2u * h - 1u - offset);
+}
+
+// Pair `t` of a disperse over blocks of `2 * h`: the first half against the second
+fn disperse_pair(t: u32, h: u32) -> vec2u {
+    let i = (t / h) * 2u * h + t % h;
+    return vec2u(i, i + h);
+}
+
+fn compare_swap(pair: vec2u) {
+    if (pair.y < params.count) {
+        let a = data[pair.x];
+        let b = data[pair.y];
+        if (less(b, a)) {
+            data[pair.x] = b;
+            data[pair.y] = a;
+        }
+    }
+}
+
+fn compare_swap_tile(start: u32, pair: vec2u) {
+    if (start + pair.y < params.count) {
+        let a = tile[pair.x];
+        let b = tile[pair.y];
+        if (less(b, a)) {
+            tile[pair.x] = b;
+            tile[pair.y] = a;
+        }
+    }
+}
+
+fn load_tile(start: u32, local: u32) {
+    for (var i = local; i < TILE_SIZE; i += WORKGROUP_SIZE) {
+        if (start + i < params.count) {
+            tile[i] = data[start + i];
+        }
+    }
+}
+
+fn store_tile(start: u32, local: u32) {
+    for (var i = local; i < TILE_SIZE; i += WORKGROUP_SIZE) {
+        if (start + i < params.count) {
+            data[start + i] = tile[i];
+        }
+    }
+}
+
+@compute
+@workgroup_size(256, 1, 1)
+fn local_sort(
+    @builtin(local_invocation_index) local: u32,
+    @builtin(workgroup_id) workgroup_id: vec3u,
+    @builtin(num_workgroups) num_workgroups: vec3u
+) {
+    let start = workgroup_index(workgroup_id, num_workgroups) * TILE_SIZE;
+    load_tile(start, local);
+
+    for (var h = 1u; h < TILE_SIZE; h <<= 1u) {
+        workgroupBarrier();
+        compare_swap_tile(start, flip_pair(local, h));
+
+        for (var d = h >> 1u; d > 0u; d >>= 1u) {
+            workgroupBarrier();
+            compare_swap_tile(start, disperse_pair(local, d));
+        }
+    }
+
+    workgroupBarrier();
+    store_tile(start, local);
+}
+
+@compute
+@workgroup_size(256, 1, 1)
+fn local_disperse(
+    @builtin(local_invocation_index) local: u32,
+    @builtin(workgroup_id) workgroup_id: vec3u,
+    @builtin(num_workgroups) num_workgroups: vec3u
+) {
+    let start = workgroup_index(workgroup_id, num_workgroups) * TILE_SIZE;
+    load_tile(start, local);
+
+    for (var d = TILE_SIZE / 2u; d > 0u; d >>= 1u) {
+        workgroupBarrier();
+        compare_swap_tile(start, disperse_pair(local, d));
+    }
+
+    workgroupBarrier();
+    store_tile(start, local);
+}
+
+@compute
+@workgroup_size(256, 1, 1)
+fn flip(
+    @builtin(local_invocation_index) local: u32,
+    @builtin(workgroup_id) workgroup_id: vec3u,
+    @builtin(num_workgroups) num_workgroups: vec3u
+) {
+    let t = workgroup_index(workgroup_id, num_workgroups) * WORKGROUP_SIZE + local;
+    compare_swap(flip_pair(t, params.half_size));
+}
+
+@compute
+@workgroup_size(256, 1, 1)
+fn disperse(
+    @builtin(local_invocation_index) local: u32,
+    @builtin(workgroup_id) workgroup_id: vec3u,
+    @builtin(num_workgroups) num_workgroups: vec3u
+) {
+    let t = workgroup_index(workgroup_id, num_workgroups) * WORKGROUP_SIZE + local;
+    compare_swap(disperse_pair(t, params.half_size));
+}
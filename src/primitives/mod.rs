@@ -0,0 +1,67 @@
+//! Built-in compute kernels for common parallel algorithms. Every primitive is set up
+//! once with an `add_*` method, which compiles its kernels and creates its temporary
+//! buffers under labels derived from the primitive's label, and is then recorded into
+//! an encoder as often as needed.
+
+pub(crate) mod bitonic_sort;
+pub(crate) mod radix_sort;
+pub(crate) mod reduce;
+pub(crate) mod scan;
+
+pub use bitonic_sort::{BitonicSort, BitonicSortPasses};
+pub use radix_sort::RadixSortPasses;
+pub use reduce::{Reduction, ReductionPasses};
+pub use scan::{Scan, ScanPasses};
+
+use crate::{buffer, shader::{self, SourceText}, BufferElements, ComputeProgram, Error, Preprocessor, Storage};
+
+/// The scalar type of the elements a primitive works on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ElementType {
+    F32,
+    U32,
+    I32
+}
+
+impl ElementType {
+    pub(crate) fn wgsl(self) -> &'static str {
+        match self {
+            ElementType::F32 => "f32",
+            ElementType::U32 => "u32",
+            ElementType::I32 => "i32"
+        }
+    }
+
+    pub(crate) fn buffer_elements(self, count: usize) -> BufferElements {
+        match self {
+            ElementType::F32 => BufferElements::of::<f32>(count),
+            ElementType::U32 => BufferElements::of::<u32>(count),
+            ElementType::I32 => BufferElements::of::<i32>(count)
+        }
+    }
+
+    /// The number of elements in a buffer, failing if it was created for another type.
+    pub(crate) fn count(self, storage: &Storage, label: &'static str) -> Result<usize, Error> {
+        match self {
+            ElementType::F32 => buffer::element_count::<f32>(storage, label),
+            ElementType::U32 => buffer::element_count::<u32>(storage, label),
+            ElementType::I32 => buffer::element_count::<i32>(storage, label)
+        }
+    }
+}
+
+/// Preprocesses the virtual file `name` and compiles it as the module `label`, so errors
+/// in user-supplied snippets point at the snippet's own lines.
+pub(crate) fn add_module<P: ComputeProgram + ?Sized>(
+    program: &mut P,
+    label: &'static str,
+    preprocessor: &Preprocessor,
+    name: &str
+) -> Result<(), Error> {
+    let source = preprocessor.process(name)?;
+    let text = SourceText::Preprocessed(&source);
+    let module = shader::parse_wgsl(label, text)?;
+    let compiled = shader::create_module(program.compute(), label, module, text)?;
+    program.storage_mut().insert_module(label, compiled);
+    Ok(())
+}
@@ -0,0 +1,212 @@
+use std::ops::Range;
+
+use crate::{labels, BindGroupItem, ComputeKernel, ComputeProgram, Error, Preprocessor};
+
+use super::{scan, ElementType, Scan};
+
+const WORKGROUP_SIZE: usize = 256;
+/// The widest digit sorted in one pass
+const DIGIT_BITS: u32 = 4;
+const RADIX: usize = 1 << DIGIT_BITS;
+
+/// The passes `ComputeProgram::radix_sort` records for one sort.
+#[derive(Clone, Debug)]
+pub struct RadixSortPasses {
+    count_kernel: &'static str,
+    scatter_kernel: &'static str,
+    offsets: &'static str,
+    /// The bind group of each digit pass
+    passes: Vec<&'static str>,
+    workgroups: u32
+}
+
+/// Splits `bits` into an even number of digits of at most `DIGIT_BITS` bits, so the
+/// keys end up back in their own buffer. Returns each digit's shift and width.
+fn digits(bits: Range<u32>) -> Vec<(u32, u32)> {
+    let total = bits.end - bits.start;
+    let passes = total.div_ceil(DIGIT_BITS).next_multiple_of(2);
+
+    let mut shift = bits.start;
+    (0..passes)
+        .map(|i| {
+            let width = total / passes + u32::from(i < total % passes);
+            shift += width;
+            (shift - width, width)
+        })
+        .collect()
+}
+
+/// Checks that `label` holds at least `count` 4-byte elements, and `u32`s or `f32`s if
+/// it was created for a specific type.
+fn check_values<P: ComputeProgram + ?Sized>(program: &P, label: &'static str, count: usize) -> Result<(), Error> {
+    match program.storage().buffer_elements.get(label) {
+        Some(elements) if elements.type_id != std::any::TypeId::of::<u32>() && elements.type_id != std::any::TypeId::of::<f32>() => {
+            Err(Error::ElementType { label, expected: elements.type_name, found: "u32 or f32" })
+        },
+        _ if program.storage().buffers[label].size() < count as u64 * 4 => Err(Error::Range {
+            label,
+            message: format!("{} bytes cannot hold a value for each of {} keys", program.storage().buffers[label].size(), count)
+        }),
+        _ => Ok(())
+    }
+}
+
+/// Compiles the kernels of a sort of the `u32` buffer `keys`, and of `values` along with
+/// it, and creates its temporary buffers under labels derived from `label`.
+pub(crate) fn create<P: ComputeProgram + ?Sized>(
+    program: &mut P,
+    label: &'static str,
+    keys: &'static str,
+    values: Option<&'static str>,
+    bits: Range<u32>
+) -> Result<(), Error> {
+    let count = ElementType::U32.count(program.storage(), keys)?;
+
+    if bits.start > bits.end || bits.end > 32 {
+        return Err(Error::Range { label: keys, message: format!("key bits {:?} are not within 0..32", bits) });
+    }
+
+    if let Some(values) = values {
+        check_values(program, values, count)?;
+    }
+
+    let count_kernel = labels::derived(label, "count_digits");
+    let scatter_kernel = labels::derived(label, "scatter");
+    let offsets = labels::derived(label, "offsets");
+
+    // Empty buffers cannot be bound, and there is nothing to sort anyway
+    if count == 0 {
+        program.storage_mut().radix_sorts.insert(label, RadixSortPasses {
+            count_kernel,
+            scatter_kernel,
+            offsets,
+            passes: Vec::new(),
+            workgroups: 0
+        });
+
+        return Ok(());
+    }
+
+    let blocks = count.div_ceil(WORKGROUP_SIZE);
+    let temporary_size = (count * 4) as u64;
+
+    let keys_temporary = labels::derived(label, "keys");
+    let values_temporary = labels::derived(label, "values");
+    let counts = labels::derived(label, "counts");
+
+    program.add_buffer(keys_temporary, wgpu::BufferUsages::STORAGE, temporary_size);
+    if values.is_some() {
+        program.add_buffer(values_temporary, wgpu::BufferUsages::STORAGE, temporary_size);
+    }
+
+    program.add_buffer_zeroed_for::<u32>(counts, wgpu::BufferUsages::STORAGE, RADIX * blocks);
+    scan::create(program, offsets, counts, ElementType::U32, Scan::Exclusive)?;
+
+    let mut passes = Vec::new();
+
+    for (i, (shift, width)) in digits(bits).into_iter().enumerate() {
+        let params = labels::derived(label, &format!("params{}", i));
+        let bind_group = labels::derived(label, &format!("pass{}", i));
+
+        // Even passes sort from the given buffers into the temporary ones, odd passes back
+        let (keys_in, keys_out, values_in, values_out) = match i % 2 {
+            0 => (keys, keys_temporary, values, Some(values_temporary)),
+            _ => (keys_temporary, keys, Some(values_temporary), values)
+        };
+
+        program.add_buffer_init(params, wgpu::BufferUsages::UNIFORM, &[count as u32, shift, width, blocks as u32]);
+
+        let mut items = vec![
+            BindGroupItem::StorageBuffer { label: keys_in, min_binding_size: 4, read_only: true },
+            BindGroupItem::StorageBuffer { label: keys_out, min_binding_size: 4, read_only: false },
+            BindGroupItem::UniformBuffer { label: params, min_binding_size: 16 },
+            BindGroupItem::StorageBuffer { label: counts, min_binding_size: 4, read_only: false },
+            BindGroupItem::StorageBuffer { label: offsets, min_binding_size: 4, read_only: true }
+        ];
+
+        if let (Some(values_in), Some(values_out)) = (values_in, values_out) {
+            items.push(BindGroupItem::StorageBuffer { label: values_in, min_binding_size: 4, read_only: true });
+            items.push(BindGroupItem::StorageBuffer { label: values_out, min_binding_size: 4, read_only: false });
+        }
+
+        program.add_bind_group(bind_group, &items);
+        passes.push(bind_group);
+    }
+
+    let module = labels::derived(label, "sort");
+
+    if let Some(&bind_group) = passes.first() {
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.add_virtual_file("radix_sort.wgsl", include_str!("radix_sort.wgsl"));
+
+        if values.is_some() {
+            preprocessor.define("VALUES", "");
+        }
+
+        super::add_module(program, module, &preprocessor, "radix_sort.wgsl")?;
+        program.add_compute_pipelines(module, &[bind_group], &[
+            ComputeKernel { label: count_kernel, entry_point: "count_digits", constants: &[] },
+            ComputeKernel { label: scatter_kernel, entry_point: "scatter", constants: &[] }
+        ], &[], None)?;
+    }
+
+    program.storage_mut().radix_sorts.insert(label, RadixSortPasses {
+        count_kernel,
+        scatter_kernel,
+        offsets,
+        passes,
+        workgroups: blocks as u32
+    });
+
+    Ok(())
+}
+
+/// Records the count, scan and scatter of every digit pass.
+pub(crate) fn record<P: ComputeProgram + ?Sized>(program: &P, encoder: &mut wgpu::CommandEncoder, label: &'static str) -> Result<(), Error> {
+    let sort = &program.storage().radix_sorts[label];
+    let invocations = sort.workgroups * WORKGROUP_SIZE as u32;
+
+    for &pass in &sort.passes {
+        program.dispatch(encoder, sort.count_kernel, &[pass], invocations)?;
+        scan::record(program, encoder, sort.offsets)?;
+        program.dispatch(encoder, sort.scatter_kernel, &[pass], invocations)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digits_cover_the_bits_in_an_even_number_of_passes() {
+        for start in 0..=32 {
+            for end in start..=32 {
+                let digits = digits(start..end);
+
+                assert_eq!(digits.len() % 2, 0, "{:?}", start..end);
+                assert_eq!(digits.len() as u32, (end - start).div_ceil(DIGIT_BITS).next_multiple_of(2), "{:?}", start..end);
+                assert!(digits.iter().all(|&(_, width)| width <= DIGIT_BITS), "{:?}", digits);
+
+                // Each digit starts where the previous one ended
+                let mut shift = start;
+                for &(digit_shift, width) in &digits {
+                    assert_eq!(digit_shift, shift, "{:?}", digits);
+                    shift += width;
+                }
+                assert_eq!(shift, end, "{:?}", digits);
+            }
+        }
+    }
+
+    #[test]
+    fn digits_of_common_ranges() {
+        assert_eq!(digits(0..32), [(0, 4), (4, 4), (8, 4), (12, 4), (16, 4), (20, 4), (24, 4), (28, 4)]);
+        assert_eq!(digits(0..16), [(0, 4), (4, 4), (8, 4), (12, 4)]);
+        assert_eq!(digits(4..12), [(4, 4), (8, 4)]);
+        assert_eq!(digits(31..32), [(31, 1), (32, 0)]);
+        assert_eq!(digits(8..21), [(8, 4), (12, 3), (15, 3), (18, 3)]);
+        assert!(digits(5..5).is_empty());
+    }
+}
@@ -0,0 +1,141 @@
+// Kernels behind `ComputeProgram::add_radix_sort`, one pass per digit of up to 4 bits.
+// `count_digits` writes how often each digit occurs in each block, digit-major, so an
+// exclusive scan of `counts` gives every block the first slot of each digit.
+// `scatter` then moves each key to that slot plus its rank among the equal digits
+// before it in the block, which keeps the sort stable.
+//
+// Defines set by the host:
+// - `VALUES`: move a `u32` or `f32` payload along with each key
+
+const WORKGROUP_SIZE: u32 = 256u;
+const RADIX: u32 = 16u;
+
+struct Params {
+    count: u32,
+    // The lowest bit of this pass's digit
+    shift: u32,
+    // The width of this pass's digit, at most 4
+    bits: u32,
+    // The number of blocks of `WORKGROUP_SIZE` keys
+    blocks: u32
+}
+
+@group(0) @binding(0)
+var<storage, read> keys_in: array<u32>;
+
+@group(0) @binding(1)
+var<storage, read_write> keys_out: array<u32>;
+
+@group(0) @binding(2)
+var<uniform> params: Params;
+
+@group(0) @binding(3)
+var<storage, read_write> counts: array<u32>;
+
+@group(0) @binding(4)
+var<storage, read> offsets: array<u32>;
+
+#ifdef VALUES
+@group(0) @binding(5)
+var<storage, read> values_in: array<u32>;
+
+@group(0) @binding(6)
+var<storage, read_write> values_out: array<u32>;
+#endif
+
+var<workgroup> histogram: array<atomic<u32>, RADIX>;
+
+// Per-thread digit counters, 16 bits per digit: digits 0 to 7 in `ranks_low` and 8 to
+// 15 in `ranks_high`, two to a component
+var<workgroup> ranks_low: array<vec4u, WORKGROUP_SIZE>;
+var<workgroup> ranks_high: array<vec4u, WORKGROUP_SIZE>;
+
+// Large dispatches are folded into the y and z dimensions
+fn block_index(workgroup_id: vec3u, num_workgroups: vec3u) -> u32 {
+    return workgroup_id.x + (workgroup_id.y + workgroup_id.z * num_workgroups.y) * num_workgroups.x;
+}
+
+fn digit(key: u32) -> u32 {
+    return (key >> params.shift) & ((1u << params.bits) - 1u);
+}
+
+@compute
+@workgroup_size(256, 1, 1)
+fn count_digits(
+    @builtin(local_invocation_index) local: u32,
+    @builtin(workgroup_id) workgroup_id: vec3u,
+    @builtin(num_workgroups) num_workgroups: vec3u
+) {
+    let block = block_index(workgroup_id, num_workgroups);
+    let index = block * WORKGROUP_SIZE + local;
+
+    if (local < RADIX) {
+        atomicStore(&histogram[local], 0u);
+    }
+    workgroupBarrier();
+
+    if (index < params.count) {
+        atomicAdd(&histogram[digit(keys_in[index])], 1u);
+    }
+    workgroupBarrier();
+
+    if (local < RADIX && block < params.blocks) {
+        counts[local * params.blocks + block] = atomicLoad(&histogram[local]);
+    }
+}
+
+@compute
+@workgroup_size(256, 1, 1)
+fn scatter(
+    @builtin(local_invocation_index) local: u32,
+    @builtin(workgroup_id) workgroup_id: vec3u,
+    @builtin(num_workgroups) num_workgroups: vec3u
+) {
+    let block = block_index(workgroup_id, num_workgroups);
+    let index = block * WORKGROUP_SIZE + local;
+    let valid = index < params.count;
+
+    var key = 0u;
+    var key_digit = RADIX;
+    if (valid) {
+        key = keys_in[index];
+        key_digit = digit(key);
+    }
+
+    // Count this thread's digit, then scan the counters across the workgroup
+    let word = key_digit / 2u;
+    let lane_shift = (key_digit % 2u) * 16u;
+    let components = vec4u(0u, 1u, 2u, 3u);
+    var low = select(vec4u(0u), vec4u(1u << lane_shift), components == vec4u(word));
+    var high = select(vec4u(0u), vec4u(1u << lane_shift), components + 4u == vec4u(word));
+
+    ranks_low[local] = low;
+    ranks_high[local] = high;
+    for (var offset = 1u; offset < WORKGROUP_SIZE; offset <<= 1u) {
+        workgroupBarrier();
+        if (local >= offset) {
+            low += ranks_low[local - offset];
+            high += ranks_high[local - offset];
+        }
+        workgroupBarrier();
+        ranks_low[local] = low;
+        ranks_high[local] = high;
+    }
+
+    if (valid) {
+        var lanes = 0u;
+        if (word < 4u) {
+            lanes = low[word];
+        } else {
+            lanes = high[word - 4u];
+        }
+
+        let rank = ((lanes >> lane_shift) & 0xffffu) - 1u;
+        let destination = offsets[key_digit * params.blocks + block] + rank;
+
+        keys_out[destination] = key;
+#ifdef VALUES
+        values_out[destination] = values_in[index];
+#endif
+    }
+}
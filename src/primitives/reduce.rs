@@ -0,0 +1,194 @@
+use crate::{labels, BindGroupItem, ComputeKernel, ComputeProgram, Error, Preprocessor};
+
+use super::ElementType;
+
+const WORKGROUP_SIZE: usize = 256;
+/// The number of elements each workgroup reduces to one item
+const TILE_SIZE: usize = WORKGROUP_SIZE * 4;
+/// The size of an intermediate item, a value and its index
+const ITEM_SIZE: u64 = 8;
+
+/// The operation of `ComputeProgram::add_reduction`.
+#[derive(Clone, Copy, Debug)]
+pub enum Reduction {
+    Sum,
+    Min,
+    Max,
+    /// The index of the smallest element, the first one if there are several
+    ArgMin,
+    /// The index of the largest element, the first one if there are several
+    ArgMax,
+    /// `combine` is WGSL defining `fn combine(a: T, b: T) -> T` for the element type,
+    /// which has to be associative and commutative. `identity` is a WGSL expression for
+    /// its identity element, e.g. `0.0` for a sum of `f32`s.
+    Custom { combine: &'static str, identity: &'static str }
+}
+
+impl Reduction {
+    fn is_arg(self) -> bool {
+        matches!(self, Reduction::ArgMin | Reduction::ArgMax)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Pass {
+    kernel: &'static str,
+    bind_group: &'static str,
+    workgroups: u32
+}
+
+/// The passes `ComputeProgram::reduce` records for one reduction.
+#[derive(Clone, Debug)]
+pub struct ReductionPasses {
+    passes: Vec<Pass>,
+    /// The one-item buffer the last pass writes
+    last: &'static str,
+    /// The offset of the result in the item, 4 for the index of arg reductions
+    offset: u64
+}
+
+fn identity(element: ElementType, reduction: Reduction) -> &'static str {
+    use ElementType::*;
+
+    match (reduction, element) {
+        (Reduction::Custom { identity, .. }, _) => identity,
+        (Reduction::Sum, F32) => "0.0",
+        (Reduction::Sum, U32) => "0u",
+        (Reduction::Sum, I32) => "0i",
+        (Reduction::Min, F32) => "bitcast<f32>(0x7f800000u)",
+        (Reduction::Min, U32) => "0xffffffffu",
+        (Reduction::Min, I32) => "2147483647i",
+        (Reduction::Max, F32) => "bitcast<f32>(0xff800000u)",
+        (Reduction::Max, U32) => "0u",
+        (Reduction::Max, I32) => "i32(-2147483648)",
+        // Padding of arg reductions is recognised by its index instead
+        (Reduction::ArgMin | Reduction::ArgMax, F32) => "0.0",
+        (Reduction::ArgMin | Reduction::ArgMax, U32) => "0u",
+        (Reduction::ArgMin | Reduction::ArgMax, I32) => "0i"
+    }
+}
+
+fn preprocessor(element: ElementType, reduction: Reduction, subgroups: bool, partials: bool) -> Preprocessor {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .add_virtual_file("reduce.wgsl", include_str!("reduce.wgsl"))
+        .define("ELEMENT", element.wgsl())
+        .define("IDENTITY", identity(element, reduction));
+
+    let (combine, subgroup_op) = match reduction {
+        Reduction::Sum => ("fn combine(a: ELEMENT, b: ELEMENT) -> ELEMENT { return a + b; }", Some("subgroupAdd")),
+        Reduction::Min => ("fn combine(a: ELEMENT, b: ELEMENT) -> ELEMENT { return min(a, b); }", Some("subgroupMin")),
+        Reduction::Max => ("fn combine(a: ELEMENT, b: ELEMENT) -> ELEMENT { return max(a, b); }", Some("subgroupMax")),
+        Reduction::ArgMin | Reduction::ArgMax => ("", None),
+        Reduction::Custom { combine, .. } => (combine, None)
+    };
+
+    preprocessor.add_virtual_file("combine.wgsl", combine);
+
+    match reduction {
+        Reduction::ArgMin => { preprocessor.define("ARG", "").define("COMPARE", "<"); },
+        Reduction::ArgMax => { preprocessor.define("ARG", "").define("COMPARE", ">"); },
+        _ => {}
+    }
+
+    if subgroups {
+        preprocessor.define("SUBGROUPS", "");
+
+        if let Some(subgroup_op) = subgroup_op {
+            preprocessor.define("SUBGROUP_OP", subgroup_op);
+        }
+    }
+
+    if partials {
+        preprocessor.define("PARTIALS", "");
+    }
+
+    preprocessor
+}
+
+/// Compiles the kernels of a reduction over `input` and creates its buffers, all under
+/// labels derived from `label`. The result goes to the one-element buffer `label`.
+pub(crate) fn create<P: ComputeProgram + ?Sized>(
+    program: &mut P,
+    label: &'static str,
+    input: &'static str,
+    element: ElementType,
+    reduction: Reduction
+) -> Result<(), Error> {
+    let count = element.count(program.storage(), input)?;
+    let subgroups = program.compute().device.features().contains(wgpu::Features::SUBGROUP);
+
+    // The number of items each pass leaves, down to one
+    let mut outputs = vec![count.div_ceil(TILE_SIZE).max(1)];
+    while outputs[outputs.len() - 1] > 1 {
+        outputs.push(outputs[outputs.len() - 1].div_ceil(TILE_SIZE));
+    }
+
+    let partials = [labels::derived(label, "partials0"), labels::derived(label, "partials1")];
+    for (partial, &size) in partials.iter().zip(&outputs) {
+        program.add_buffer(partial, wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC, size as u64 * ITEM_SIZE);
+    }
+
+    let mut passes = Vec::with_capacity(outputs.len());
+    let mut source = input;
+    let mut source_count = count;
+
+    for (i, &output) in outputs.iter().enumerate() {
+        let params = labels::derived(label, &format!("params{}", i));
+        let bind_group = labels::derived(label, &format!("pass{}", i));
+        let destination = partials[i % 2];
+
+        program.add_buffer_init(params, wgpu::BufferUsages::UNIFORM, &[source_count as u32, 0, 0, 0]);
+        program.add_bind_group(bind_group, &[
+            BindGroupItem::StorageBuffer { label: source, min_binding_size: if i == 0 { 4 } else { ITEM_SIZE }, read_only: true },
+            BindGroupItem::StorageBuffer { label: destination, min_binding_size: ITEM_SIZE, read_only: false },
+            BindGroupItem::UniformBuffer { label: params, min_binding_size: 4 }
+        ]);
+
+        let kernel = labels::derived(label, if i == 0 { "input" } else { "partials" });
+
+        // The first pass reads elements and the rest read items, so they need two modules
+        if i < 2 {
+            add_kernel(program, kernel, bind_group, preprocessor(element, reduction, subgroups, i > 0))?;
+        }
+
+        passes.push(Pass { kernel, bind_group, workgroups: output as u32 });
+        source = destination;
+        source_count = output;
+    }
+
+    let element_type = if reduction.is_arg() { ElementType::U32 } else { element };
+    program.add_buffer(label, wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST, 4);
+    program.storage_mut().buffer_elements.insert(label, element_type.buffer_elements(1));
+
+    program.storage_mut().reductions.insert(label, ReductionPasses {
+        passes,
+        last: source,
+        offset: if reduction.is_arg() { 4 } else { 0 }
+    });
+
+    Ok(())
+}
+
+fn add_kernel<P: ComputeProgram + ?Sized>(
+    program: &mut P,
+    kernel: &'static str,
+    bind_group: &'static str,
+    preprocessor: Preprocessor
+) -> Result<(), Error> {
+    super::add_module(program, kernel, &preprocessor, "reduce.wgsl")?;
+    program.add_compute_pipelines(kernel, &[bind_group], &[ComputeKernel { label: kernel, entry_point: "reduce", constants: &[] }], &[], None)
+}
+
+/// Records every pass of the reduction `label` and the copy of its result.
+pub(crate) fn record<P: ComputeProgram + ?Sized>(program: &P, encoder: &mut wgpu::CommandEncoder, label: &'static str) -> Result<(), Error> {
+    let reduction = &program.storage().reductions[label];
+
+    for pass in &reduction.passes {
+        program.dispatch(encoder, pass.kernel, &[pass.bind_group], pass.workgroups * WORKGROUP_SIZE as u32)?;
+    }
+
+    let storage = program.storage();
+    encoder.copy_buffer_to_buffer(&storage.buffers[reduction.last], reduction.offset, &storage.buffers[label], 0, 4);
+    Ok(())
+}
@@ -0,0 +1,133 @@
+// Kernels behind `ComputeProgram::add_reduction`. Every workgroup reduces one tile of
+// `TILE_SIZE` items to a single item; passes are repeated until one item is left.
+//
+// Defines set by the host:
+// - `ELEMENT`: the scalar type, `f32`, `u32` or `i32`
+// - `IDENTITY`: the identity of `combine`
+// - `PARTIALS`: the source holds the items of a previous pass instead of elements
+// - `ARG` and `COMPARE`: keep the index of the first value that wins `COMPARE`
+// - `SUBGROUPS`, and `SUBGROUP_OP` for reductions with a builtin subgroup operation
+#include "combine.wgsl"
+
+const WORKGROUP_SIZE: u32 = 256u;
+const ITEMS_PER_THREAD: u32 = 4u;
+const TILE_SIZE: u32 = WORKGROUP_SIZE * ITEMS_PER_THREAD;
+const NONE: u32 = 0xffffffffu;
+
+struct Item {
+    value: ELEMENT,
+    // The index of `value` in the input for `ARG` reductions, `NONE` for padding
+    index: u32
+}
+
+struct Params {
+    // The number of elements or items in `source`
+    count: u32
+}
+
+#ifdef PARTIALS
+@group(0) @binding(0)
+var<storage, read> source: array<Item>;
+#else
+@group(0) @binding(0)
+var<storage, read> source: array<ELEMENT>;
+#endif
+
+@group(0) @binding(1)
+var<storage, read_write> destination: array<Item>;
+
+@group(0) @binding(2)
+var<uniform> params: Params;
+
+var<workgroup> items: array<Item, WORKGROUP_SIZE>;
+
+fn load(i: u32) -> Item {
+#ifdef PARTIALS
+    return source[i];
+#else
+    return Item(source[i], i);
+#endif
+}
+
+fn combine_items(a: Item, b: Item) -> Item {
+#ifdef ARG
+    let b_wins = b.value COMPARE a.value || (b.value == a.value && b.index < a.index);
+    if (b.index != NONE && (a.index == NONE || b_wins)) {
+        return b;
+    }
+    return a;
+#else
+    return Item(combine(a.value, b.value), NONE);
+#endif
+}
+
+#ifdef SUBGROUPS
+fn subgroup_reduce(item: Item, subgroup_size: u32) -> Item {
+#ifdef SUBGROUP_OP
+    return Item(SUBGROUP_OP(item.value), NONE);
+#else
+    // Only lane 0 reads lanes that were all combined from valid lanes
+    var result = item;
+    for (var offset = subgroup_size / 2u; offset > 0u; offset >>= 1u) {
+        let other = Item(subgroupShuffleDown(result.value, offset), subgroupShuffleDown(result.index, offset));
+        result = combine_items(result, other);
+    }
+    return result;
+#endif
+}
+#endif
+
+@compute
+@workgroup_size(256, 1, 1)
+fn reduce(
+    @builtin(local_invocation_index) local: u32,
+    @builtin(workgroup_id) workgroup_id: vec3u,
+#ifdef SUBGROUPS
+    @builtin(subgroup_size) subgroup_size: u32,
+    @builtin(subgroup_invocation_id) lane: u32,
+    @builtin(subgroup_id) subgroup_id: u32,
+    @builtin(num_subgroups) num_subgroups: u32,
+#endif
+    @builtin(num_workgroups) num_workgroups: vec3u
+) {
+    // Large dispatches are folded into the y and z dimensions
+    let group = workgroup_id.x + (workgroup_id.y + workgroup_id.z * num_workgroups.y) * num_workgroups.x;
+    let base = group * TILE_SIZE;
+
+    var result = Item(IDENTITY, NONE);
+    for (var i = 0u; i < ITEMS_PER_THREAD; i++) {
+        let index = base + i * WORKGROUP_SIZE + local;
+        if (index < params.count) {
+            result = combine_items(result, load(index));
+        }
+    }
+
+#ifdef SUBGROUPS
+    result = subgroup_reduce(result, subgroup_size);
+    if (lane == 0u) {
+        items[subgroup_id] = result;
+    }
+    workgroupBarrier();
+
+    if (local == 0u) {
+        result = items[0];
+        for (var i = 1u; i < num_subgroups; i++) {
+            result = combine_items(result, items[i]);
+        }
+    }
+#else
+    items[local] = result;
+    for (var stride = WORKGROUP_SIZE / 2u; stride > 0u; stride >>= 1u) {
+        workgroupBarrier();
+        if (local < stride) {
+            items[local] = combine_items(items[local], items[local + stride]);
+        }
+    }
+    result = items[0];
+#endif
+
+    // The first workgroup always writes, so empty inputs reduce to the identity
+    if (local == 0u && (base < params.count || group == 0u)) {
+        destination[group] = result;
+    }
+}
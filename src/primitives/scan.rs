@@ -0,0 +1,140 @@
+use crate::{labels, BindGroupItem, ComputeKernel, ComputeProgram, Error, Preprocessor};
+
+use super::ElementType;
+
+const WORKGROUP_SIZE: usize = 256;
+/// The number of elements each workgroup scans
+const BLOCK_SIZE: usize = WORKGROUP_SIZE * 4;
+
+/// Whether `ComputeProgram::add_scan` includes each element in its own sum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scan {
+    /// `output[i] = input[0] + ... + input[i]`
+    Inclusive,
+    /// `output[i] = input[0] + ... + input[i - 1]`, starting from zero
+    Exclusive
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Level {
+    scan: &'static str,
+    /// The bind group adding the scanned totals of the level above, if there is one
+    add: Option<&'static str>,
+    workgroups: u32
+}
+
+/// The passes `ComputeProgram::scan` records for one scan.
+#[derive(Clone, Debug)]
+pub struct ScanPasses {
+    scan_kernel: &'static str,
+    add_kernel: &'static str,
+    levels: Vec<Level>
+}
+
+/// Compiles the kernels of a scan over `input` and creates its buffers, all under labels
+/// derived from `label`. The result goes to the buffer `label`, as long as `input`.
+pub(crate) fn create<P: ComputeProgram + ?Sized>(
+    program: &mut P,
+    label: &'static str,
+    input: &'static str,
+    element: ElementType,
+    scan: Scan
+) -> Result<(), Error> {
+    let count = element.count(program.storage(), input)?;
+    let usage = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST;
+
+    program.add_buffer(label, usage, (count.max(1) * 4) as u64);
+    program.storage_mut().buffer_elements.insert(label, element.buffer_elements(count));
+
+    // The number of elements scanned at each level; every level above the first scans
+    // the block totals of the one below
+    let mut counts = vec![count];
+    while counts[counts.len() - 1] > BLOCK_SIZE {
+        counts.push(counts[counts.len() - 1].div_ceil(BLOCK_SIZE));
+    }
+
+    let module = labels::derived(label, "scan");
+    let scan_kernel = labels::derived(label, "scan_blocks");
+    let add_kernel = labels::derived(label, "add_offsets");
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .add_virtual_file("scan.wgsl", include_str!("scan.wgsl"))
+        .define("ELEMENT", element.wgsl());
+
+    super::add_module(program, module, &preprocessor, "scan.wgsl")?;
+
+    let mut levels = Vec::with_capacity(counts.len());
+
+    for (i, &level_count) in counts.iter().enumerate() {
+        let blocks = level_count.div_ceil(BLOCK_SIZE).max(1);
+        let params = labels::derived(label, &format!("params{}", i));
+        let totals = labels::derived(label, &format!("totals{}", i));
+        let bind_group = labels::derived(label, &format!("scan{}", i));
+
+        let (source, destination) = match i {
+            0 => (input, label),
+            _ => (labels::derived(label, &format!("totals{}", i - 1)), labels::derived(label, &format!("offsets{}", i)))
+        };
+
+        if i > 0 {
+            program.add_buffer(destination, wgpu::BufferUsages::STORAGE, (level_count * 4) as u64);
+        }
+
+        // Every level above the first is exclusive, so block `b` is offset by the totals
+        // of the blocks before it
+        let exclusive = i > 0 || scan == Scan::Exclusive;
+        program.add_buffer_init(params, wgpu::BufferUsages::UNIFORM, &[level_count as u32, exclusive as u32, 0, 0]);
+        program.add_buffer(totals, wgpu::BufferUsages::STORAGE, (blocks * 4) as u64);
+
+        program.add_bind_group(bind_group, &[
+            BindGroupItem::StorageBuffer { label: source, min_binding_size: 4, read_only: true },
+            BindGroupItem::StorageBuffer { label: destination, min_binding_size: 4, read_only: false },
+            BindGroupItem::UniformBuffer { label: params, min_binding_size: 8 },
+            BindGroupItem::StorageBuffer { label: totals, min_binding_size: 4, read_only: false }
+        ]);
+
+        levels.push(Level { scan: bind_group, add: None, workgroups: blocks as u32 });
+    }
+
+    // Created once every level's buffers exist
+    for i in 0..levels.len() - 1 {
+        let add = labels::derived(label, &format!("add{}", i));
+        let destination = if i == 0 { label } else { labels::derived(label, &format!("offsets{}", i)) };
+
+        program.add_bind_group(add, &[
+            BindGroupItem::StorageBuffer { label: labels::derived(label, &format!("offsets{}", i + 1)), min_binding_size: 4, read_only: true },
+            BindGroupItem::StorageBuffer { label: destination, min_binding_size: 4, read_only: false },
+            BindGroupItem::UniformBuffer { label: labels::derived(label, &format!("params{}", i)), min_binding_size: 8 }
+        ]);
+
+        levels[i].add = Some(add);
+    }
+
+    let kernel = |label, entry_point| ComputeKernel { label, entry_point, constants: &[] };
+    program.add_compute_pipelines(module, &[levels[0].scan], &[kernel(scan_kernel, "scan_blocks")], &[], None)?;
+
+    if let Some(add) = levels[0].add {
+        program.add_compute_pipelines(module, &[add], &[kernel(add_kernel, "add_offsets")], &[], None)?;
+    }
+
+    program.storage_mut().scans.insert(label, ScanPasses { scan_kernel, add_kernel, levels });
+    Ok(())
+}
+
+/// Records the block scans of every level, then adds the offsets back down.
+pub(crate) fn record<P: ComputeProgram + ?Sized>(program: &P, encoder: &mut wgpu::CommandEncoder, label: &'static str) -> Result<(), Error> {
+    let scan = &program.storage().scans[label];
+
+    for level in &scan.levels {
+        program.dispatch(encoder, scan.scan_kernel, &[level.scan], level.workgroups * WORKGROUP_SIZE as u32)?;
+    }
+
+    for level in scan.levels.iter().rev() {
+        if let Some(add) = level.add {
+            program.dispatch(encoder, scan.add_kernel, &[add], level.workgroups * WORKGROUP_SIZE as u32)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,117 @@
+// Kernels behind `ComputeProgram::add_scan`. `scan_blocks` scans each block of
+// `BLOCK_SIZE` elements on its own and writes the block's total to `totals`. The totals
+// are scanned the same way one level up, and `add_offsets` then adds the scanned totals
+// back to every element of their block.
+//
+// Defines set by the host:
+// - `ELEMENT`: the scalar type, `f32`, `u32` or `i32`
+
+const WORKGROUP_SIZE: u32 = 256u;
+const ITEMS_PER_THREAD: u32 = 4u;
+const BLOCK_SIZE: u32 = WORKGROUP_SIZE * ITEMS_PER_THREAD;
+
+struct Params {
+    // The number of elements in `source` for `scan_blocks`, in `destination` for `add_offsets`
+    count: u32,
+    // Whether `scan_blocks` leaves out each element from its own sum
+    exclusive: u32
+}
+
+// The elements for `scan_blocks`, the scanned block totals for `add_offsets`
+@group(0) @binding(0)
+var<storage, read> source: array<ELEMENT>;
+
+@group(0) @binding(1)
+var<storage, read_write> destination: array<ELEMENT>;
+
+@group(0) @binding(2)
+var<uniform> params: Params;
+
+@group(0) @binding(3)
+var<storage, read_write> totals: array<ELEMENT>;
+
+var<workgroup> sums: array<ELEMENT, WORKGROUP_SIZE>;
+
+// Large dispatches are folded into the y and z dimensions
+fn block_index(workgroup_id: vec3u, num_workgroups: vec3u) -> u32 {
+    return workgroup_id.x + (workgroup_id.y + workgroup_id.z * num_workgroups.y) * num_workgroups.x;
+}
+
+@compute
+@workgroup_size(256, 1, 1)
+fn scan_blocks(
+    @builtin(local_invocation_index) local: u32,
+    @builtin(workgroup_id) workgroup_id: vec3u,
+    @builtin(num_workgroups) num_workgroups: vec3u
+) {
+    let block = block_index(workgroup_id, num_workgroups);
+    let first = block * BLOCK_SIZE + local * ITEMS_PER_THREAD;
+
+    // Each thread scans its own consecutive elements first
+    var values: array<ELEMENT, ITEMS_PER_THREAD>;
+    var total = ELEMENT();
+    for (var i = 0u; i < ITEMS_PER_THREAD; i++) {
+        if (first + i < params.count) {
+            values[i] = source[first + i];
+        }
+        total += values[i];
+    }
+
+    // Then the threads' totals are scanned across the workgroup, Hillis-Steele style
+    sums[local] = total;
+    for (var offset = 1u; offset < WORKGROUP_SIZE; offset <<= 1u) {
+        workgroupBarrier();
+        var previous = ELEMENT();
+        if (local >= offset) {
+            previous = sums[local - offset];
+        }
+        workgroupBarrier();
+        sums[local] += previous;
+    }
+    workgroupBarrier();
+
+    var running = ELEMENT();
+    if (local > 0u) {
+        running = sums[local - 1u];
+    }
+
+    for (var i = 0u; i < ITEMS_PER_THREAD; i++) {
+        let value = values[i];
+        if (params.exclusive == 0u) {
+            running += value;
+        }
+        if (first + i < params.count) {
+            destination[first + i] = running;
+        }
+        if (params.exclusive != 0u) {
+            running += value;
+        }
+    }
+
+    let blocks = max((params.count + BLOCK_SIZE - 1u) / BLOCK_SIZE, 1u);
+    if (local == 0u && block < blocks) {
+        totals[block] = sums[WORKGROUP_SIZE - 1u];
+    }
+}
+
+@compute
+@workgroup_size(256, 1, 1)
+fn add_offsets(
+    @builtin(local_invocation_index) local: u32,
+    @builtin(workgroup_id) workgroup_id: vec3u,
+    @builtin(num_workgroups) num_workgroups: vec3u
+) {
+    let block = block_index(workgroup_id, num_workgroups);
+    let first = block * BLOCK_SIZE + local * ITEMS_PER_THREAD;
+
+    if (first >= params.count) {
+        return;
+    }
+
+    let offset = source[block];
+    for (var i = 0u; i < ITEMS_PER_THREAD; i++) {
+        if (first + i < params.count) {
+            destination[first + i] += offset;
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::{path::PathBuf, time::SystemTime};
+
+use crate::PreprocessedSource;
+
+/// The files on disk a module loaded with `add_module_from_path` was built from.
+#[derive(Debug, Clone)]
+pub struct ModuleFiles {
+    /// The name the module was loaded with, passed to the preprocessor again on reload
+    pub name: String,
+    /// Every file that contributed to the module, including `#include`s, with its
+    /// modification time when the module was last compiled
+    pub files: Vec<(PathBuf, Option<SystemTime>)>
+}
+
+fn modified(path: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl ModuleFiles {
+    pub(crate) fn new(name: String, source: &PreprocessedSource) -> Self {
+        let files = source.files
+            .iter()
+            .filter_map(|file| file.path.clone())
+            .map(|path| {
+                let modified = modified(&path);
+                (path, modified)
+            })
+            .collect();
+
+        Self { name, files }
+    }
+
+    pub(crate) fn is_changed(&self) -> bool {
+        self.files.iter().any(|(path, time)| modified(path) != *time)
+    }
+}
+
+/// Watches the directories of every module loaded with `add_module_from_path`, so that
+/// `reload_changed_modules` only touches the file system after something changed.
+#[cfg(feature = "hot-reload")]
+pub struct ModuleWatcher {
+    watcher: notify::RecommendedWatcher,
+    receiver: flume::Receiver<()>,
+    directories: std::collections::HashSet<PathBuf>
+}
+
+#[cfg(feature = "hot-reload")]
+impl ModuleWatcher {
+    pub(crate) fn new() -> Result<Self, crate::Error> {
+        let (sender, receiver) = flume::unbounded();
+
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if event.is_ok_and(|e| e.kind.is_modify() || e.kind.is_create()) {
+                let _ = sender.send(());
+            }
+        }).map_err(watch_error)?;
+
+        Ok(Self { watcher, receiver, directories: Default::default() })
+    }
+
+    pub(crate) fn watch(&mut self, files: &ModuleFiles) -> Result<(), crate::Error> {
+        use notify::Watcher;
+
+        // Editors often save by replacing the file, so watch its directory instead
+        for (path, _) in &files.files {
+            let directory = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => PathBuf::from(".")
+            };
+
+            if self.directories.insert(directory.clone()) {
+                self.watcher
+                    .watch(&directory, notify::RecursiveMode::NonRecursive)
+                    .map_err(watch_error)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether anything changed since the last call.
+    pub(crate) fn take_events(&self) -> bool {
+        self.receiver.drain().count() > 0
+    }
+}
+
+#[cfg(feature = "hot-reload")]
+fn watch_error(error: notify::Error) -> crate::Error {
+    crate::Error::Io {
+        path: error.paths.first().cloned().unwrap_or_default(),
+        source: std::io::Error::other(error.to_string())
+    }
+}
@@ -0,0 +1,212 @@
+use std::{borrow::Cow, ops::Range};
+
+use codespan_reporting::{
+    diagnostic::{Diagnostic, Label},
+    files::SimpleFiles,
+    term::{self, termcolor::NoColor}
+};
+
+use crate::{Compute, Error, PreprocessedSource};
+
+/// The text a module was compiled from, used to annotate diagnostics.
+#[derive(Clone, Copy)]
+pub(crate) enum SourceText<'a> {
+    /// SPIR-V and naga IR have no text to point at
+    None,
+    File { name: &'a str, source: &'a str },
+    Preprocessed(&'a PreprocessedSource)
+}
+
+impl SourceText<'_> {
+    fn compiled(&self) -> &str {
+        match self {
+            SourceText::None => "",
+            SourceText::File { source, .. } => source,
+            SourceText::Preprocessed(source) => &source.source
+        }
+    }
+
+    /// Renders a codespan diagnostic. Spans of preprocessed sources are moved back to
+    /// the files they came from.
+    fn emit(&self, message: String, spans: Vec<(Range<usize>, String)>, notes: Vec<String>) -> String {
+        let mut files = SimpleFiles::new();
+
+        let labels = match self {
+            SourceText::None => Vec::new(),
+            SourceText::File { name, source } => {
+                let file = files.add(name.to_string(), source.to_string());
+                spans
+                    .into_iter()
+                    .map(|(range, message)| Label::primary(file, range).with_message(message))
+                    .collect()
+            },
+            SourceText::Preprocessed(source) => {
+                let ids: Vec<usize> = source.files
+                    .iter()
+                    .map(|file| files.add(file.name.clone(), file.source.clone()))
+                    .collect();
+                spans
+                    .into_iter()
+                    .filter_map(|(range, message)| {
+                        let (file, range) = source.original_range(range)?;
+                        Some(Label::primary(ids[file], range).with_message(message))
+                    })
+                    .collect()
+            }
+        };
+
+        let diagnostic = Diagnostic::error()
+            .with_message(message)
+            .with_labels(labels)
+            .with_notes(notes);
+
+        let mut writer = NoColor::new(Vec::new());
+        term::emit(&mut writer, &term::Config::default(), &files, &diagnostic).expect("cannot write diagnostic");
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+}
+
+/// Whether `range` of `source` lies within the arguments of a `@workgroup_size` attribute.
+fn in_workgroup_size(source: &str, range: &Range<usize>) -> bool {
+    let before = &source[..range.start];
+    before
+        .rfind("@workgroup_size")
+        .is_some_and(|start| !before[start..].contains(')'))
+}
+
+pub(crate) fn parse_wgsl(label: &'static str, text: SourceText) -> Result<naga::Module, Error> {
+    naga::front::wgsl::parse_str(text.compiled()).map_err(|error| {
+        let spans: Vec<_> = error
+            .labels()
+            .filter_map(|(span, message)| Some((span.to_range()?, message.to_string())))
+            .collect();
+
+        // naga stores workgroup sizes as plain numbers, so overrides cannot be used there
+        let mut notes = Vec::new();
+        if error.message().contains("override") && spans.iter().any(|(range, _)| in_workgroup_size(text.compiled(), range)) {
+            notes.push(
+                "`@workgroup_size` cannot use `override` constants, as its value must be known when the module is parsed. \
+                Use a `const`, or compile one module per size with the preprocessor's `#define`".to_owned()
+            );
+        }
+
+        Error::Shader { label, message: text.emit(error.message().to_string(), spans, notes) }
+    })
+}
+
+pub(crate) fn parse_glsl(
+    label: &'static str,
+    source: &str,
+    stage: naga::ShaderStage,
+    defines: &[(&str, &str)]
+) -> Result<naga::Module, Error> {
+    let options = naga::front::glsl::Options {
+        stage,
+        defines: defines
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    };
+
+    naga::front::glsl::Frontend::default()
+        .parse(&options, source)
+        .map_err(|error| {
+            let text = SourceText::File { name: label, source };
+            let message = error.errors
+                .iter()
+                .map(|error| {
+                    let spans = error.meta.to_range().map(|range| (range, String::new())).into_iter().collect();
+                    text.emit(error.kind.to_string(), spans, Vec::new())
+                })
+                .collect();
+
+            Error::Shader { label, message }
+        })
+}
+
+pub(crate) fn parse_spirv(label: &'static str, bytes: &[u8]) -> Result<naga::Module, Error> {
+    naga::front::spv::parse_u8_slice(bytes, &naga::front::spv::Options::default())
+        .map_err(|error| Error::Shader { label, message: SourceText::None.emit(error.to_string(), Vec::new(), Vec::new()) })
+}
+
+/// Validates a module with the capabilities of `compute`'s device, the same way wgpu
+/// would when creating the shader module.
+pub(crate) fn validate(
+    compute: &Compute,
+    label: &'static str,
+    module: &naga::Module,
+    text: SourceText
+) -> Result<naga::valid::ModuleInfo, Error> {
+    use naga::valid::Capabilities as Caps;
+
+    let features = compute.device.features();
+    let downlevel = compute.adapter.get_downlevel_capabilities().flags;
+
+    let mut capabilities = Caps::empty();
+    capabilities.set(Caps::PUSH_CONSTANT, features.contains(wgpu::Features::PUSH_CONSTANTS));
+    capabilities.set(Caps::FLOAT64, features.contains(wgpu::Features::SHADER_F64));
+    capabilities.set(Caps::PRIMITIVE_INDEX, features.contains(wgpu::Features::SHADER_PRIMITIVE_INDEX));
+    capabilities.set(
+        Caps::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING | Caps::SAMPLER_NON_UNIFORM_INDEXING,
+        features.contains(wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING)
+    );
+    capabilities.set(
+        Caps::UNIFORM_BUFFER_AND_STORAGE_TEXTURE_ARRAY_NON_UNIFORM_INDEXING,
+        features.contains(wgpu::Features::UNIFORM_BUFFER_AND_STORAGE_TEXTURE_ARRAY_NON_UNIFORM_INDEXING)
+    );
+    capabilities.set(Caps::STORAGE_TEXTURE_16BIT_NORM_FORMATS, features.contains(wgpu::Features::TEXTURE_FORMAT_16BIT_NORM));
+    capabilities.set(Caps::MULTIVIEW, features.contains(wgpu::Features::MULTIVIEW));
+    capabilities.set(Caps::EARLY_DEPTH_TEST, features.contains(wgpu::Features::SHADER_EARLY_DEPTH_TEST));
+    capabilities.set(Caps::SHADER_INT64, features.contains(wgpu::Features::SHADER_INT64));
+    capabilities.set(Caps::MULTISAMPLED_SHADING, downlevel.contains(wgpu::DownlevelFlags::MULTISAMPLED_SHADING));
+    capabilities.set(Caps::DUAL_SOURCE_BLENDING, features.contains(wgpu::Features::DUAL_SOURCE_BLENDING));
+    capabilities.set(Caps::CUBE_ARRAY_TEXTURES, downlevel.contains(wgpu::DownlevelFlags::CUBE_ARRAY_TEXTURES));
+    capabilities.set(Caps::SUBGROUP, features.intersects(wgpu::Features::SUBGROUP | wgpu::Features::SUBGROUP_VERTEX));
+    capabilities.set(Caps::SUBGROUP_BARRIER, features.contains(wgpu::Features::SUBGROUP_BARRIER));
+
+    let mut subgroup_stages = naga::valid::ShaderStages::empty();
+    subgroup_stages.set(
+        naga::valid::ShaderStages::COMPUTE | naga::valid::ShaderStages::FRAGMENT,
+        features.contains(wgpu::Features::SUBGROUP)
+    );
+    subgroup_stages.set(naga::valid::ShaderStages::VERTEX, features.contains(wgpu::Features::SUBGROUP_VERTEX));
+
+    let mut validator = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), capabilities);
+    validator.subgroup_stages(subgroup_stages);
+    if capabilities.contains(Caps::SUBGROUP) {
+        validator.subgroup_operations(naga::valid::SubgroupOperationSet::all());
+    }
+
+    validator.validate(module).map_err(|error| {
+        let spans = error
+            .spans()
+            .filter_map(|(span, message)| Some((span.to_range()?, message.clone())))
+            .collect();
+
+        let mut notes = Vec::new();
+        let mut source: &dyn std::error::Error = error.as_inner();
+        while let Some(next) = source.source() {
+            notes.push(next.to_string());
+            source = next;
+        }
+
+        Error::Shader { label, message: text.emit(error.as_inner().to_string(), spans, notes) }
+    })
+}
+
+/// Validates `module` and creates a wgpu shader module from it.
+pub(crate) fn create_module(
+    compute: &Compute,
+    label: &'static str,
+    module: naga::Module,
+    text: SourceText
+) -> Result<(wgpu::ShaderModule, naga::Module), Error> {
+    validate(compute, label, &module, text)?;
+
+    let shader_module = compute.device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Naga(Cow::Owned(module.clone()))
+    });
+
+    Ok((shader_module, module))
+}
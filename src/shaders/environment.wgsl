@@ -0,0 +1,194 @@
+// Kernels behind `ComputeProgram::add_environment_maps`. Every kernel writes one texel
+// of each of the six faces of a cube, stored as a 2D array with one layer per face.
+// Group 0 holds the destination and parameters of a pass, group 1 the sampled cube.
+
+const PI: f32 = 3.14159265359;
+
+struct Params {
+    roughness: f32,
+    samples: u32,
+    // The face size of mip 0 of the environment cube
+    source_size: f32,
+    // The environment mip sampled for irradiance
+    mip: f32
+}
+
+@group(0) @binding(0)
+var destination: texture_storage_2d_array<rgba16float, write>;
+
+@group(0) @binding(1)
+var<uniform> params: Params;
+
+// Only bound for `equirect_to_cube`
+@group(0) @binding(2)
+var equirect: texture_2d<f32>;
+
+@group(1) @binding(0)
+var environment: texture_cube<f32>;
+
+@group(1) @binding(1)
+var environment_sampler: sampler;
+
+// The direction through `uv` of a cube face, in the +X, -X, +Y, -Y, +Z, -Z face order
+fn cube_direction(face: u32, uv: vec2f) -> vec3f {
+    let st = uv * 2.0 - 1.0;
+
+    switch face {
+        case 0u: { return normalize(vec3f(1.0, -st.y, -st.x)); }
+        case 1u: { return normalize(vec3f(-1.0, -st.y, st.x)); }
+        case 2u: { return normalize(vec3f(st.x, 1.0, st.y)); }
+        case 3u: { return normalize(vec3f(st.x, -1.0, -st.y)); }
+        case 4u: { return normalize(vec3f(st.x, -st.y, 1.0)); }
+        default: { return normalize(vec3f(-st.x, -st.y, -1.0)); }
+    }
+}
+
+fn face_uv(id: vec3u, size: vec2u) -> vec2f {
+    return (vec2f(id.xy) + 0.5) / vec2f(size);
+}
+
+// Bilinear filtering with `textureLoad`, so non-filterable float formats work too.
+// Wraps around horizontally and clamps at the poles.
+fn load_equirect(uv: vec2f) -> vec4f {
+    let size = vec2i(textureDimensions(equirect));
+    let p = uv * vec2f(size) - 0.5;
+    let base = vec2i(floor(p));
+    let f = fract(p);
+
+    let x0 = ((base.x % size.x) + size.x) % size.x;
+    let x1 = (x0 + 1) % size.x;
+    let y0 = clamp(base.y, 0, size.y - 1);
+    let y1 = clamp(base.y + 1, 0, size.y - 1);
+
+    let top = mix(textureLoad(equirect, vec2i(x0, y0), 0), textureLoad(equirect, vec2i(x1, y0), 0), f.x);
+    let bottom = mix(textureLoad(equirect, vec2i(x0, y1), 0), textureLoad(equirect, vec2i(x1, y1), 0), f.x);
+    return mix(top, bottom, f.y);
+}
+
+@compute
+@workgroup_size(8, 8, 1)
+fn equirect_to_cube(@builtin(global_invocation_id) id: vec3u) {
+    let size = textureDimensions(destination);
+    if (any(id.xy >= size)) {
+        return;
+    }
+
+    let dir = cube_direction(id.z, face_uv(id, size));
+    let uv = vec2f(atan2(dir.z, dir.x) / (2.0 * PI) + 0.5, acos(clamp(dir.y, -1.0, 1.0)) / PI);
+
+    textureStore(destination, id.xy, id.z, load_equirect(uv));
+}
+
+// Averages 2x2 texels of the previous mip, the only mip of the bound cube. Texel centers
+// of this mip fall on the corners between those texels, so one bilinear sample does it.
+@compute
+@workgroup_size(8, 8, 1)
+fn downsample(@builtin(global_invocation_id) id: vec3u) {
+    let size = textureDimensions(destination);
+    if (any(id.xy >= size)) {
+        return;
+    }
+
+    let dir = cube_direction(id.z, face_uv(id, size));
+    textureStore(destination, id.xy, id.z, textureSampleLevel(environment, environment_sampler, dir, 0.0));
+}
+
+fn radical_inverse(i: u32) -> f32 {
+    var bits = i;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return f32(bits) * 2.3283064365386963e-10;
+}
+
+fn importance_sample_ggx(xi: vec2f, n: vec3f, roughness: f32) -> vec3f {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    let h = vec3f(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+
+    let up = select(vec3f(1.0, 0.0, 0.0), vec3f(0.0, 0.0, 1.0), abs(n.z) < 0.999);
+    let tangent = normalize(cross(up, n));
+    let bitangent = cross(n, tangent);
+    return normalize(tangent * h.x + bitangent * h.y + n * h.z);
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Convolves the environment with the GGX distribution for `params.roughness`, assuming
+// the view direction equals the normal. Samples are taken from the environment mip
+// whose texels cover about the solid angle of each sample, to avoid aliasing.
+@compute
+@workgroup_size(8, 8, 1)
+fn prefilter_specular(@builtin(global_invocation_id) id: vec3u) {
+    let size = textureDimensions(destination);
+    if (any(id.xy >= size)) {
+        return;
+    }
+
+    let n = cube_direction(id.z, face_uv(id, size));
+    let texel_solid_angle = 4.0 * PI / (6.0 * params.source_size * params.source_size);
+
+    var color = vec3f(0.0);
+    var weight = 0.0;
+
+    for (var i = 0u; i < params.samples; i++) {
+        let xi = vec2f(f32(i) / f32(params.samples), radical_inverse(i));
+        let h = importance_sample_ggx(xi, n, params.roughness);
+        let l = normalize(2.0 * dot(n, h) * h - n);
+        let n_dot_l = dot(n, l);
+
+        if (n_dot_l > 0.0) {
+            let n_dot_h = max(dot(n, h), 0.0);
+            let pdf = distribution_ggx(n_dot_h, params.roughness) / 4.0 + 0.0001;
+            let sample_solid_angle = 1.0 / (f32(params.samples) * pdf + 0.0001);
+            let mip = select(0.5 * log2(sample_solid_angle / texel_solid_angle), 0.0, params.roughness == 0.0);
+
+            color += textureSampleLevel(environment, environment_sampler, l, max(mip, 0.0)).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+
+    textureStore(destination, id.xy, id.z, vec4f(color / max(weight, 0.0001), 1.0));
+}
+
+// Convolves the environment with a cosine lobe over the hemisphere around each normal
+@compute
+@workgroup_size(8, 8, 1)
+fn irradiance(@builtin(global_invocation_id) id: vec3u) {
+    let size = textureDimensions(destination);
+    if (any(id.xy >= size)) {
+        return;
+    }
+
+    let n = cube_direction(id.z, face_uv(id, size));
+    let up = select(vec3f(0.0, 0.0, 1.0), vec3f(0.0, 1.0, 0.0), abs(n.y) < 0.999);
+    let right = normalize(cross(up, n));
+    let forward = cross(n, right);
+
+    let steps = params.samples;
+    var color = vec3f(0.0);
+
+    for (var i = 0u; i < steps * 4u; i++) {
+        let phi = 2.0 * PI * (f32(i) + 0.5) / f32(steps * 4u);
+
+        for (var j = 0u; j < steps; j++) {
+            let theta = 0.5 * PI * (f32(j) + 0.5) / f32(steps);
+            let tangent = vec3f(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let dir = tangent.x * right + tangent.y * forward + tangent.z * n;
+
+            color += textureSampleLevel(environment, environment_sampler, dir, params.mip).rgb * cos(theta) * sin(theta);
+        }
+    }
+
+    color = PI * color / f32(steps * steps * 4u);
+    textureStore(destination, id.xy, id.z, vec4f(color, 1.0));
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+use bytemuck::Pod;
+
+use crate::{buffer, Error};
+
+type MapResult = Result<(), wgpu::BufferAsyncError>;
+
+/// Looks up the ring of a buffer, failing if `add_staging_ring` was never called for it.
+pub(crate) fn ring_mut<'a>(rings: &'a mut HashMap<&'static str, StagingRing>, label: &'static str) -> Result<&'a mut StagingRing, Error> {
+    rings.get_mut(label).ok_or_else(|| Error::Range {
+        label,
+        message: "the buffer has no staging ring, call add_staging_ring first".into()
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SlotState {
+    Free,
+    /// A copy was recorded but the buffer has not been mapped yet
+    Copied(u64),
+    Mapping(u64),
+    Mapped(u64)
+}
+
+struct Slot {
+    buffer: wgpu::Buffer,
+    state: SlotState,
+    sender: flume::Sender<MapResult>,
+    receiver: flume::Receiver<MapResult>
+}
+
+/// A ring of staging buffers for one buffer, so that readbacks of several frames can be in
+/// flight at once. Created by `ComputeProgram::add_staging_ring`.
+pub struct StagingRing {
+    slots: Vec<Slot>,
+    next: usize,
+    frame: u64,
+    /// The frame of the last readback returned by `try_read_latest`
+    last_read: Option<u64>
+}
+
+impl StagingRing {
+    pub(crate) fn new(device: &wgpu::Device, size: u64, frames: usize) -> Self {
+        let slots = (0..frames.max(1))
+            .map(|_| {
+                let (sender, receiver) = flume::bounded(1);
+                Slot {
+                    buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                        label: None,
+                        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                        size,
+                        mapped_at_creation: false
+                    }),
+                    state: SlotState::Free,
+                    sender,
+                    receiver
+                }
+            })
+            .collect();
+
+        Self { slots, next: 0, frame: 0, last_read: None }
+    }
+
+    /// The number of staging buffers in the ring.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Records a copy of `source` into the next free staging buffer. Returns `false`
+    /// without recording anything when every staging buffer is still in use.
+    pub(crate) fn copy(&mut self, encoder: &mut wgpu::CommandEncoder, source: &wgpu::Buffer) -> bool {
+        let count = self.slots.len();
+        let Some(index) = (0..count)
+            .map(|i| (self.next + i) % count)
+            .find(|&i| self.slots[i].state == SlotState::Free)
+        else {
+            return false;
+        };
+
+        let slot = &mut self.slots[index];
+        encoder.copy_buffer_to_buffer(source, 0, &slot.buffer, 0, source.size().min(slot.buffer.size()));
+
+        slot.state = SlotState::Copied(self.frame);
+        self.frame += 1;
+        self.next = (index + 1) % count;
+        true
+    }
+
+    /// Starts mapping every staging buffer copied into since the last call.
+    pub(crate) fn prepare(&mut self) {
+        for slot in &mut self.slots {
+            if let SlotState::Copied(frame) = slot.state {
+                let sender = slot.sender.clone();
+                slot.buffer.slice(..).map_async(wgpu::MapMode::Read, move |v| { let _ = sender.send(v); });
+                slot.state = SlotState::Mapping(frame);
+            }
+        }
+    }
+
+    /// Reads the most recent completed readback into `dst`, releasing it and every older
+    /// one. Returns `false` if no readback newer than the last one read has completed.
+    pub(crate) fn try_read_latest<T: Pod>(&mut self, label: &'static str, dst: &mut [T]) -> Result<bool, Error> {
+        let mut error = None;
+
+        for slot in &mut self.slots {
+            if let SlotState::Mapping(frame) = slot.state {
+                match slot.receiver.try_recv() {
+                    Ok(Ok(())) => slot.state = SlotState::Mapped(frame),
+                    Ok(Err(source)) => {
+                        slot.state = SlotState::Free;
+                        error = Some(Error::Map { label, source });
+                    },
+                    Err(_) => {}
+                }
+            }
+        }
+
+        if let Some(error) = error {
+            return Err(error);
+        }
+
+        let latest = self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(i, slot)| match slot.state {
+                SlotState::Mapped(frame) => Some((frame, i)),
+                _ => None
+            })
+            .max();
+
+        let Some((frame, index)) = latest else {
+            return Ok(false);
+        };
+
+        let num_bytes = std::mem::size_of_val(dst) as u64;
+        let is_newer = self.last_read.is_none_or(|last| frame > last);
+
+        if is_newer {
+            let buffer = &self.slots[index].buffer;
+            if buffer::aligned_size(num_bytes) > buffer.size() {
+                return Err(Error::Range {
+                    label,
+                    message: format!("cannot read {} bytes from a staging buffer of {} bytes", num_bytes, buffer.size())
+                });
+            }
+
+            buffer::read_mapped(buffer, 0, dst);
+            self.last_read = Some(frame);
+        }
+
+        for slot in &mut self.slots {
+            if let SlotState::Mapped(slot_frame) = slot.state {
+                if slot_frame <= frame {
+                    slot.buffer.unmap();
+                    slot.state = SlotState::Free;
+                }
+            }
+        }
+
+        Ok(is_newer)
+    }
+}
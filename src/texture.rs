@@ -0,0 +1,291 @@
+use std::path::{Path, PathBuf};
+
+use wgpu::util::TextureDataOrder;
+
+use crate::Error;
+
+/// Where `add_texture_from_image` and the texture container loaders read a file from.
+#[derive(Clone, Copy, Debug)]
+pub enum ImageSource<'a> {
+    Path(&'a Path),
+    /// The contents of an image file, e.g. from `include_bytes!`
+    Bytes(&'a [u8])
+}
+
+impl<'a> From<&'a Path> for ImageSource<'a> {
+    fn from(path: &'a Path) -> Self {
+        Self::Path(path)
+    }
+}
+
+impl<'a> From<&'a PathBuf> for ImageSource<'a> {
+    fn from(path: &'a PathBuf) -> Self {
+        Self::Path(path)
+    }
+}
+
+impl<'a> From<&'a str> for ImageSource<'a> {
+    fn from(path: &'a str) -> Self {
+        Self::Path(Path::new(path))
+    }
+}
+
+impl<'a> From<&'a [u8]> for ImageSource<'a> {
+    fn from(bytes: &'a [u8]) -> Self {
+        Self::Bytes(bytes)
+    }
+}
+
+impl<'a, const N: usize> From<&'a [u8; N]> for ImageSource<'a> {
+    fn from(bytes: &'a [u8; N]) -> Self {
+        Self::Bytes(bytes)
+    }
+}
+
+impl ImageSource<'_> {
+    /// The contents of the file.
+    #[cfg(any(feature = "image", feature = "ktx2", feature = "dds"))]
+    pub(crate) fn read(&self) -> Result<std::borrow::Cow<'_, [u8]>, Error> {
+        use std::borrow::Cow;
+
+        match *self {
+            ImageSource::Path(path) => std::fs::read(path)
+                .map(Cow::Owned)
+                .map_err(|source| Error::Io { path: path.to_owned(), source }),
+            ImageSource::Bytes(bytes) => Ok(Cow::Borrowed(bytes))
+        }
+    }
+}
+
+/// A box of texels in one mip of a texture, written by `ComputeProgram::write_texture`.
+/// For 3D textures `origin.z` and `size.depth_or_array_layers` select depth slices,
+/// otherwise array layers.
+#[derive(Clone, Copy, Debug)]
+pub struct TextureRegion {
+    pub mip_level: u32,
+    pub origin: wgpu::Origin3d,
+    pub size: wgpu::Extent3d
+}
+
+impl TextureRegion {
+    /// The whole of `mip_level` for a texture of `size` (the size of mip 0).
+    pub fn mip(size: wgpu::Extent3d, dimension: wgpu::TextureDimension, mip_level: u32) -> Self {
+        Self {
+            mip_level,
+            origin: wgpu::Origin3d::ZERO,
+            size: size.mip_level_size(mip_level, dimension)
+        }
+    }
+}
+
+/// How one 2D image of a texture is laid out in a buffer for `copy_texture_to_buffer`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ImageLayout {
+    pub width: u32,
+    pub height: u32,
+    /// The size of one tightly packed row
+    pub bytes_per_row: u32,
+    /// `bytes_per_row` rounded up to `COPY_BYTES_PER_ROW_ALIGNMENT`
+    pub padded_bytes_per_row: u32
+}
+
+impl ImageLayout {
+    /// The layout of `mip` of a texture, failing for formats that cannot be copied
+    /// texel by texel.
+    pub fn of_mip(label: &'static str, texture: &wgpu::Texture, mip: u32) -> Result<Self, Error> {
+        let format = texture.format();
+        let error = |message: String| Error::Texture { label, message };
+
+        if format.is_compressed() {
+            return Err(error(format!("cannot copy texels of compressed format {:?}", format)));
+        }
+
+        let Some(block_size) = format.block_copy_size(None) else {
+            return Err(error(format!("{:?} has no single copyable aspect", format)));
+        };
+
+        if mip >= texture.mip_level_count() {
+            return Err(error(format!("mip {} is out of range, the texture has {}", mip, texture.mip_level_count())));
+        }
+
+        let size = texture.size().mip_level_size(mip, texture.dimension());
+        let bytes_per_row = size.width * block_size;
+
+        Ok(Self {
+            width: size.width,
+            height: size.height,
+            bytes_per_row,
+            padded_bytes_per_row: bytes_per_row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+        })
+    }
+
+    /// The size of a staging buffer holding the padded image.
+    pub fn padded_size(&self) -> u64 {
+        self.padded_bytes_per_row as u64 * self.height as u64
+    }
+
+    /// The size of the tightly packed image.
+    pub fn size(&self) -> usize {
+        self.bytes_per_row as usize * self.height as usize
+    }
+
+    pub fn buffer_layout(&self) -> wgpu::ImageDataLayout {
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(self.padded_bytes_per_row),
+            rows_per_image: Some(self.height)
+        }
+    }
+
+    /// Copies the rows of a padded image into tightly packed `dst`.
+    pub fn strip_padding(&self, padded: &[u8], dst: &mut [u8]) {
+        let row = self.bytes_per_row as usize;
+
+        for (dst, src) in dst.chunks_exact_mut(row).zip(padded.chunks(self.padded_bytes_per_row as usize)) {
+            dst.copy_from_slice(&src[..row]);
+        }
+    }
+}
+
+/// Checks that a texture has the layer (or depth slice for 3D textures) `layer` at `mip`.
+pub(crate) fn check_layer(label: &'static str, texture: &wgpu::Texture, mip: u32, layer: u32) -> Result<(), Error> {
+    let layers = match texture.dimension() {
+        wgpu::TextureDimension::D3 => texture.size().mip_level_size(mip, wgpu::TextureDimension::D3).depth_or_array_layers,
+        _ => texture.depth_or_array_layers()
+    };
+
+    if layer >= layers {
+        return Err(Error::Texture { label, message: format!("layer {} is out of range, mip {} has {}", layer, mip, layers) });
+    }
+
+    Ok(())
+}
+
+/// Computes how the data for `region` is laid out for `queue.write_texture`, returning
+/// the layout, the size to copy and the number of bytes expected. Compressed formats are
+/// handled in whole blocks, so the region may cover the partial blocks at the edges.
+pub(crate) fn upload_layout(
+    label: &'static str,
+    texture: &wgpu::Texture,
+    region: &TextureRegion
+) -> Result<(wgpu::ImageDataLayout, wgpu::Extent3d, usize), Error> {
+    let format = texture.format();
+    let error = |message: String| Error::Texture { label, message };
+
+    let Some(block_size) = format.block_copy_size(None) else {
+        return Err(error(format!("{:?} has no single copyable aspect", format)));
+    };
+
+    if region.mip_level >= texture.mip_level_count() {
+        return Err(error(format!("mip {} is out of range, the texture has {}", region.mip_level, texture.mip_level_count())));
+    }
+
+    let mip_size = texture.size()
+        .mip_level_size(region.mip_level, texture.dimension())
+        .physical_size(format);
+
+    let (block_width, block_height) = format.block_dimensions();
+    let size = region.size.physical_size(format);
+    let origin = region.origin;
+
+    if !origin.x.is_multiple_of(block_width) || !origin.y.is_multiple_of(block_height) {
+        return Err(error(format!("origin {:?} is not aligned to {}x{} blocks", origin, block_width, block_height)));
+    }
+
+    let fits = origin.x + size.width <= mip_size.width
+        && origin.y + size.height <= mip_size.height
+        && origin.z + size.depth_or_array_layers <= mip_size.depth_or_array_layers;
+
+    if !fits {
+        return Err(error(format!(
+            "region at {:?} of size {:?} is out of bounds of mip {} with size {:?}",
+            origin, region.size, region.mip_level, mip_size
+        )));
+    }
+
+    let bytes_per_row = size.width / block_width * block_size;
+    let rows_per_image = size.height / block_height;
+    let len = bytes_per_row as usize * rows_per_image as usize * size.depth_or_array_layers as usize;
+
+    let layout = wgpu::ImageDataLayout {
+        offset: 0,
+        bytes_per_row: Some(bytes_per_row),
+        rows_per_image: Some(rows_per_image)
+    };
+
+    Ok((layout, size, len))
+}
+
+/// The view dimension matching a texture: `Cube` or `CubeArray` for textures created as
+/// cube maps, otherwise derived from the texture dimension and layer count.
+pub(crate) fn view_dimension(texture: &wgpu::Texture, cube: bool) -> wgpu::TextureViewDimension {
+    let layers = texture.depth_or_array_layers();
+
+    match texture.dimension() {
+        wgpu::TextureDimension::D1 => wgpu::TextureViewDimension::D1,
+        wgpu::TextureDimension::D3 => wgpu::TextureViewDimension::D3,
+        wgpu::TextureDimension::D2 if cube && layers == 6 => wgpu::TextureViewDimension::Cube,
+        wgpu::TextureDimension::D2 if cube => wgpu::TextureViewDimension::CubeArray,
+        wgpu::TextureDimension::D2 if layers > 1 => wgpu::TextureViewDimension::D2Array,
+        wgpu::TextureDimension::D2 => wgpu::TextureViewDimension::D2
+    }
+}
+
+/// Uploads every mip of every layer of a texture from `data`. Each image is tightly
+/// packed, and 3D textures hold all depth slices of a mip in one image.
+pub(crate) fn write_all(
+    queue: &wgpu::Queue,
+    label: &'static str,
+    texture: &wgpu::Texture,
+    order: TextureDataOrder,
+    data: &[u8]
+) -> Result<(), Error> {
+    let (dimension, size) = (texture.dimension(), texture.size());
+    let layers = match dimension {
+        wgpu::TextureDimension::D3 => 1,
+        _ => size.depth_or_array_layers
+    };
+
+    let images: Vec<(u32, u32)> = match order {
+        TextureDataOrder::LayerMajor => (0..layers).flat_map(|layer| (0..texture.mip_level_count()).map(move |mip| (layer, mip))).collect(),
+        _ => (0..texture.mip_level_count()).flat_map(|mip| (0..layers).map(move |layer| (layer, mip))).collect()
+    };
+
+    let mut uploads = Vec::new();
+    let mut offset = 0;
+
+    for (layer, mip_level) in images {
+        let mut region = TextureRegion::mip(size, dimension, mip_level);
+        if dimension != wgpu::TextureDimension::D3 {
+            region.origin.z = layer;
+            region.size.depth_or_array_layers = 1;
+        }
+
+        let (layout, copy_size, len) = upload_layout(label, texture, &region)?;
+        uploads.push((region, layout, copy_size, offset..offset + len));
+        offset += len;
+    }
+
+    if offset != data.len() {
+        return Err(Error::Texture {
+            label,
+            message: format!("expected {} bytes of texel data, got {}", offset, data.len())
+        });
+    }
+
+    for (region, layout, copy_size, range) in uploads {
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: region.mip_level,
+                origin: region.origin,
+                aspect: wgpu::TextureAspect::All
+            },
+            &data[range],
+            layout,
+            copy_size
+        );
+    }
+
+    Ok(())
+}
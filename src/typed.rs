@@ -0,0 +1,241 @@
+use std::marker::PhantomData;
+
+use bytemuck::Pod;
+use wgpu::BufferUsages;
+
+use crate::{BindGroupItem, BufferElements, ComputeProgram, Error};
+
+/// A fixed-length buffer of `T`s in `Storage::buffers`, created by
+/// `ComputeProgram::add_gpu_buffer`.
+///
+/// The handle only holds the label and length, so it is `Copy` and the buffer itself
+/// stays owned by the program.
+pub struct GpuBuffer<T: Pod> {
+    label: &'static str,
+    len: usize,
+    marker: PhantomData<fn() -> T>
+}
+
+impl<T: Pod> Clone for GpuBuffer<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Pod> Copy for GpuBuffer<T> {}
+
+impl<T: Pod> GpuBuffer<T> {
+    pub(crate) fn new(label: &'static str, len: usize) -> Self {
+        Self { label, len, marker: PhantomData }
+    }
+
+    pub fn label(&self) -> &'static str {
+        self.label
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// A storage buffer binding with `min_binding_size` set to the size of one `T`.
+    pub fn storage_binding(&self, read_only: bool) -> BindGroupItem {
+        BindGroupItem::StorageBuffer { label: self.label, min_binding_size: element_size::<T>(), read_only }
+    }
+
+    /// A uniform buffer binding with `min_binding_size` set to the size of one `T`.
+    pub fn uniform_binding(&self) -> BindGroupItem {
+        BindGroupItem::UniformBuffer { label: self.label, min_binding_size: element_size::<T>() }
+    }
+
+    /// Reads the whole buffer back, blocking until the GPU is done with it.
+    pub fn read(&self, program: &impl ComputeProgram) -> Result<Vec<T>, Error> {
+        if self.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        pollster::block_on(program.read_buffer::<T>(self.label))
+    }
+
+    /// Writes `data` starting at element `index`. For elements smaller than 4 bytes, the
+    /// write has to start and end on a multiple of 4 bytes, e.g. four `u8`s at a time.
+    pub fn write(&self, program: &impl ComputeProgram, index: usize, data: &[T]) -> Result<(), Error> {
+        check_bounds(self.label, index, data.len(), self.len)?;
+        check_alignment::<T>(self.label, index, data.len())?;
+        program.write_buffer(self.label, (index * std::mem::size_of::<T>()) as u64, data)
+    }
+}
+
+/// A growable buffer of `T`s in `Storage::buffers`, created by `ComputeProgram::add_gpu_vec`.
+///
+/// Pushing past the capacity allocates a buffer of at least twice the size under the same
+/// label and copies the old contents over on the GPU. Bind groups holding the old buffer
+/// must be recreated with `add_bind_group` after a reallocation.
+///
+/// Writes go through `Queue::write_buffer`, which needs 4-byte aligned offsets and sizes.
+/// Elements whose size is a multiple of 4 bytes can always be pushed; smaller ones, like
+/// `u8` or `u16`, only in runs that start and end on a 4-byte boundary.
+pub struct GpuVec<T: Pod> {
+    label: &'static str,
+    usage: BufferUsages,
+    len: usize,
+    capacity: usize,
+    marker: PhantomData<fn() -> T>
+}
+
+impl<T: Pod> GpuVec<T> {
+    pub(crate) fn new(label: &'static str, usage: BufferUsages, capacity: usize) -> Self {
+        Self { label, usage, len: 0, capacity, marker: PhantomData }
+    }
+
+    pub fn label(&self) -> &'static str {
+        self.label
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// A fixed-length view of the elements pushed so far.
+    pub fn as_buffer(&self) -> GpuBuffer<T> {
+        GpuBuffer::new(self.label, self.len)
+    }
+
+    pub fn storage_binding(&self, read_only: bool) -> BindGroupItem {
+        self.as_buffer().storage_binding(read_only)
+    }
+
+    /// Appends one element. Returns `true` if the buffer was reallocated.
+    pub fn push(&mut self, program: &mut impl ComputeProgram, value: T) -> Result<bool, Error> {
+        self.extend(program, &[value])
+    }
+
+    /// Appends `data`. Returns `true` if the buffer was reallocated.
+    pub fn extend(&mut self, program: &mut impl ComputeProgram, data: &[T]) -> Result<bool, Error> {
+        // Checked before reallocating, so a failed write leaves the buffer as it was
+        check_alignment::<T>(self.label, self.len, data.len())?;
+
+        let needed = self.len + data.len();
+        let reallocated = needed > self.capacity;
+
+        if reallocated {
+            self.reserve_exact(program, needed.max(self.capacity * 2));
+        }
+
+        program.write_buffer(self.label, (self.len * std::mem::size_of::<T>()) as u64, data)?;
+        self.set_len(program, needed);
+        Ok(reallocated)
+    }
+
+    /// Overwrites elements starting at `index`, which must lie within the current length.
+    pub fn write(&self, program: &impl ComputeProgram, index: usize, data: &[T]) -> Result<(), Error> {
+        self.as_buffer().write(program, index, data)
+    }
+
+    /// Shortens the vector to `len` elements. The buffer keeps its capacity.
+    pub fn truncate(&mut self, program: &mut impl ComputeProgram, len: usize) {
+        if len < self.len {
+            self.set_len(program, len);
+        }
+    }
+
+    pub fn clear(&mut self, program: &mut impl ComputeProgram) {
+        self.truncate(program, 0);
+    }
+
+    /// Reads the pushed elements back, blocking until the GPU is done with them.
+    pub fn read(&self, program: &impl ComputeProgram) -> Result<Vec<T>, Error> {
+        self.as_buffer().read(program)
+    }
+
+    /// Grows the buffer to hold exactly `capacity` elements, keeping its contents.
+    pub fn reserve_exact(&mut self, program: &mut impl ComputeProgram, capacity: usize) {
+        if capacity <= self.capacity {
+            return;
+        }
+
+        let device = program.compute().device.clone();
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: buffer_size::<T>(capacity),
+            usage: self.usage,
+            mapped_at_creation: false
+        });
+
+        let used = buffer_size::<T>(self.len);
+        if self.len > 0 {
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: None
+            });
+            encoder.copy_buffer_to_buffer(&program.storage().buffers[self.label], 0, &buffer, 0, used);
+            program.compute().queue.submit(Some(encoder.finish()));
+        }
+
+        program.storage_mut().buffers.insert(self.label, buffer);
+        self.capacity = capacity;
+    }
+
+    fn set_len(&mut self, program: &mut impl ComputeProgram, len: usize) {
+        self.len = len;
+        program.storage_mut().buffer_elements.insert(self.label, BufferElements::of::<T>(len));
+    }
+}
+
+/// The usages every typed buffer gets on top of the requested ones, so it can be read,
+/// written and grown.
+pub(crate) const TYPED_USAGES: BufferUsages = BufferUsages::COPY_SRC.union(BufferUsages::COPY_DST);
+
+/// The size of a buffer holding `count` elements. Never zero, so empty buffers can still
+/// be bound.
+pub(crate) fn buffer_size<T: Pod>(count: usize) -> u64 {
+    ((count.max(1) * std::mem::size_of::<T>()) as u64).next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT)
+}
+
+fn element_size<T: Pod>() -> u64 {
+    std::mem::size_of::<T>().max(1) as u64
+}
+
+fn check_bounds(label: &'static str, index: usize, count: usize, len: usize) -> Result<(), Error> {
+    if index + count > len {
+        return Err(Error::Range {
+            label,
+            message: format!("elements {}..{} are out of bounds of a buffer of {} elements", index, index + count, len)
+        });
+    }
+
+    Ok(())
+}
+
+/// Checks that writing `count` elements at `index` covers a 4-byte aligned range of bytes,
+/// which only fails for elements smaller than 4 bytes.
+fn check_alignment<T: Pod>(label: &'static str, index: usize, count: usize) -> Result<(), Error> {
+    if count == 0 {
+        return Ok(());
+    }
+
+    let size = std::mem::size_of::<T>();
+    let (start, end) = ((index * size) as u64, ((index + count) * size) as u64);
+
+    if !start.is_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT) || !end.is_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT) {
+        return Err(Error::Range {
+            label,
+            message: format!(
+                "elements {}..{} cover bytes {}..{}, but writes must start and end on a multiple of {} bytes",
+                index, index + count, start, end, wgpu::COPY_BUFFER_ALIGNMENT
+            )
+        });
+    }
+
+    Ok(())
+}
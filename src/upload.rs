@@ -0,0 +1,121 @@
+use std::sync::Arc;
+
+struct Chunk {
+    buffer: Arc<wgpu::Buffer>,
+    offset: u64
+}
+
+/// Uploads data through reusable mapped buffers instead of `queue.write_buffer`.
+///
+/// Writes are sub-allocated from chunks that are mapped while the CPU fills them, and a
+/// `copy_buffer_to_buffer` into the target is recorded for each write. After the encoder
+/// is submitted, `recall` maps the chunks again so they can be reused once the GPU is done.
+pub struct UploadBelt {
+    chunk_size: u64,
+    /// Mapped chunks that still have room
+    active: Vec<Chunk>,
+    /// Unmapped chunks waiting for their copies to be submitted
+    closed: Vec<Chunk>,
+    /// Mapped chunks that are empty again
+    free: Vec<Chunk>,
+    sender: flume::Sender<Chunk>,
+    receiver: flume::Receiver<Chunk>
+}
+
+impl Default for UploadBelt {
+    fn default() -> Self {
+        Self::new(1 << 20)
+    }
+}
+
+impl UploadBelt {
+    /// Creates a belt allocating chunks of `chunk_size` bytes. Writes larger than that
+    /// get a chunk of their own.
+    pub fn new(chunk_size: u64) -> Self {
+        let (sender, receiver) = flume::unbounded();
+
+        Self {
+            chunk_size,
+            active: Vec::new(),
+            closed: Vec::new(),
+            free: Vec::new(),
+            sender,
+            receiver
+        }
+    }
+
+    /// Records a write of `data` into `target` at `offset`. Both `offset` and the size of
+    /// `data` must be multiples of `COPY_BUFFER_ALIGNMENT`.
+    pub fn write(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::Buffer,
+        offset: u64,
+        data: &[u8]
+    ) {
+        let size = data.len() as u64;
+        if size == 0 {
+            return;
+        }
+
+        let index = match self.active.iter().position(|chunk| chunk.offset + size <= chunk.buffer.size()) {
+            Some(index) => index,
+            None => {
+                self.free.extend(self.receiver.try_iter());
+
+                let chunk = match self.free.iter().position(|chunk| size <= chunk.buffer.size()) {
+                    Some(index) => self.free.swap_remove(index),
+                    None => Chunk {
+                        buffer: Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+                            label: None,
+                            size: self.chunk_size.max(size).next_multiple_of(wgpu::MAP_ALIGNMENT),
+                            usage: wgpu::BufferUsages::MAP_WRITE | wgpu::BufferUsages::COPY_SRC,
+                            mapped_at_creation: true
+                        })),
+                        offset: 0
+                    }
+                };
+
+                self.active.push(chunk);
+                self.active.len() - 1
+            }
+        };
+
+        let chunk = &mut self.active[index];
+        chunk.buffer
+            .slice(chunk.offset..chunk.offset + size)
+            .get_mapped_range_mut()
+            .copy_from_slice(data);
+
+        encoder.copy_buffer_to_buffer(&chunk.buffer, chunk.offset, target, offset, size);
+        chunk.offset = (chunk.offset + size).next_multiple_of(wgpu::MAP_ALIGNMENT);
+    }
+
+    /// Unmaps the chunks written into so far. Call this before submitting the encoders
+    /// passed to `write`.
+    pub fn finish(&mut self) {
+        for chunk in self.active.drain(..) {
+            chunk.buffer.unmap();
+            self.closed.push(chunk);
+        }
+    }
+
+    /// Maps the chunks closed by `finish` again, making them available once the GPU has
+    /// executed their copies. Call this after submitting. The chunks are only reused after
+    /// the device has been polled, e.g. with `Maintain::Poll`, or by a later submit.
+    pub fn recall(&mut self) {
+        for mut chunk in self.closed.drain(..) {
+            let sender = self.sender.clone();
+            let buffer = chunk.buffer.clone();
+            chunk.offset = 0;
+
+            buffer.slice(..).map_async(wgpu::MapMode::Write, move |result| {
+                // Chunks that fail to map are dropped
+                if result.is_ok() {
+                    let _ = sender.send(chunk);
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::sync::Arc;
+
+use pollster::FutureExt;
+
+use tiny_wgpu::{Compute, ComputeProgram, Storage};
+
+pub struct TestProgram {
+    pub storage: Storage,
+    pub compute: Compute
+}
+
+impl ComputeProgram for TestProgram {
+    fn storage(&self) -> &Storage {
+        &self.storage
+    }
+
+    fn storage_mut(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+
+    fn compute(&self) -> &Compute {
+        &self.compute
+    }
+}
+
+/// A program on the default adapter, or the backends in `WGPU_BACKEND` if it is set.
+///
+/// The GPU tests are `#[ignore]`d so that `cargo test` passes on machines without an
+/// adapter. Run them with `cargo test -- --ignored`, where a missing adapter fails them.
+pub fn program() -> TestProgram {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::PRIMARY),
+        ..Default::default()
+    });
+
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions::default())
+        .block_on()
+        .expect("no adapter available, the GPU tests need one");
+
+    let (device, queue) = adapter.request_device(&wgpu::DeviceDescriptor {
+        label: None,
+        required_features: wgpu::Features::empty(),
+        required_limits: wgpu::Limits::default()
+    }, None).block_on().expect("failed to create a device");
+
+    let compute = Compute {
+        instance: Arc::new(instance),
+        adapter: Arc::new(adapter),
+        device: Arc::new(device),
+        queue: Arc::new(queue)
+    };
+
+    TestProgram { storage: Storage::default(), compute }
+}
+
+/// A xorshift generator, so every run uses the same data
+#[allow(dead_code)]
+pub fn random_u32s(count: usize, seed: u32) -> Vec<u32> {
+    let mut state = seed.max(1);
+
+    (0..count)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        })
+        .collect()
+}
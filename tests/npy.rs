@@ -0,0 +1,35 @@
+mod common;
+
+use std::fmt::Debug;
+
+use pollster::FutureExt;
+use wgpu::BufferUsages;
+
+use tiny_wgpu::{ComputeProgram, NpyElement};
+
+const USAGE: BufferUsages = BufferUsages::STORAGE.union(BufferUsages::COPY_SRC);
+
+/// Saves `data` from one buffer and loads it into another, through a file named `name`.
+fn round_trip<T: NpyElement + PartialEq + Debug>(program: &mut common::TestProgram, name: &'static str, data: &[T]) {
+    let path = std::env::temp_dir().join(format!("tiny_wgpu_{}_{}.npy", std::process::id(), name));
+
+    program.add_buffer_init(name, USAGE, data);
+    program.save_buffer_npy::<T>(name, &path).unwrap();
+
+    program.add_buffer_from_npy::<T>("loaded", &path, USAGE).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(program.read_buffer::<T>("loaded").block_on().unwrap(), data, "{}", name);
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn round_trips_arrays_with_odd_byte_lengths() {
+    let mut program = common::program();
+
+    round_trip::<u8>(&mut program, "u1", &[1, 2, 3]);
+    round_trip::<i8>(&mut program, "i1", &[-1, 0, 1, 2, -3]);
+    round_trip::<u16>(&mut program, "u2", &[1, 60000, 3]);
+    round_trip::<i16>(&mut program, "i2", &[-1, 2, -30000]);
+    round_trip::<[u8; 3]>(&mut program, "u1x3", &[[1, 2, 3], [4, 5, 6], [7, 8, 9]]);
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use std::ops::Range;
+
+use pollster::FutureExt;
+use wgpu::BufferUsages;
+
+use tiny_wgpu::ComputeProgram;
+
+use common::{random_u32s, TestProgram};
+
+const USAGE: BufferUsages = BufferUsages::STORAGE.union(BufferUsages::COPY_SRC);
+
+fn mask(bits: &Range<u32>) -> u32 {
+    let width = bits.end - bits.start;
+    if width == 32 { u32::MAX } else { ((1u32 << width) - 1) << bits.start }
+}
+
+fn sort(program: &TestProgram, label: &'static str) {
+    let mut encoder = program.compute.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: None
+    });
+    program.radix_sort(&mut encoder, label).unwrap();
+    program.compute.queue.submit(Some(encoder.finish()));
+}
+
+/// Sorts `keys` on the GPU with their indices as values, and checks the result against
+/// a stable sort on the CPU.
+fn check(program: &mut TestProgram, keys: &[u32], bits: Range<u32>) {
+    let indices: Vec<u32> = (0..keys.len() as u32).collect();
+
+    program.add_buffer_init("keys", USAGE, keys);
+    program.add_buffer_init("indices", USAGE, &indices);
+    program.add_radix_sort("sort", "keys", Some("indices"), bits.clone()).unwrap();
+    sort(program, "sort");
+
+    let sorted_keys = program.read_buffer::<u32>("keys").block_on().unwrap();
+    let sorted_indices = program.read_buffer::<u32>("indices").block_on().unwrap();
+
+    // Only the bits in the range are compared, and equal digits keep their order
+    let mask = mask(&bits);
+    let mut expected = indices.clone();
+    expected.sort_by_key(|&i| keys[i as usize] & mask);
+
+    let expected_keys: Vec<u32> = expected.iter().map(|&i| keys[i as usize]).collect();
+
+    assert_eq!(sorted_indices, expected, "{} keys, bits {:?}: values differ", keys.len(), bits);
+    assert_eq!(sorted_keys, expected_keys, "{} keys, bits {:?}: keys differ", keys.len(), bits);
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn sorts_empty_input() {
+    let mut program = common::program();
+    check(&mut program, &[], 0..32);
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn sorts_partial_and_multi_level_sizes() {
+    let mut program = common::program();
+
+    // Partial blocks, a single block and several levels of digit offsets
+    for count in [1, 255, 256, 257, 1000, 70_001, 1 << 20] {
+        check(&mut program, &random_u32s(count, count as u32), 0..32);
+    }
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn sorts_partial_bit_ranges() {
+    let mut program = common::program();
+
+    check(&mut program, &random_u32s(50_000, 5), 4..12);
+    check(&mut program, &random_u32s(50_000, 6), 8..21);
+    check(&mut program, &random_u32s(1000, 7), 31..32);
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn keeps_equal_keys_in_order() {
+    let mut program = common::program();
+
+    // Narrow key ranges with many duplicates, and odd digit counts
+    let small: Vec<u32> = random_u32s(50_000, 3).iter().map(|key| key & 0xff).collect();
+    check(&mut program, &small, 0..8);
+    check(&mut program, &small, 0..3);
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn sorts_f32_values_with_their_keys() {
+    let mut program = common::program();
+
+    let keys = random_u32s(100_000, 7);
+    let values: Vec<f32> = keys.iter().map(|&key| key as f32 * 0.5).collect();
+
+    program.add_buffer_init("keys", USAGE, &keys);
+    program.add_buffer_init("values", USAGE, &values);
+    program.add_radix_sort("sort", "keys", Some("values"), 0..32).unwrap();
+    sort(&program, "sort");
+
+    let sorted_keys = program.read_buffer::<u32>("keys").block_on().unwrap();
+    let sorted_values = program.read_buffer::<f32>("values").block_on().unwrap();
+
+    let mut expected = keys.clone();
+    expected.sort();
+
+    assert_eq!(sorted_keys, expected);
+    for (key, value) in sorted_keys.iter().zip(&sorted_values) {
+        assert_eq!(*key as f32 * 0.5, *value);
+    }
+}
@@ -0,0 +1,23 @@
+mod common;
+
+use pollster::FutureExt;
+use wgpu::BufferUsages;
+
+use tiny_wgpu::ComputeProgram;
+
+const USAGE: BufferUsages = BufferUsages::STORAGE.union(BufferUsages::COPY_SRC);
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn reads_buffers_with_unaligned_lengths() {
+    let mut program = common::program();
+
+    program.add_buffer_init::<u8>("bytes", USAGE, &[1, 2, 3]);
+    program.add_buffer_init::<i16>("shorts", USAGE, &[-1, 2, -3]);
+
+    assert_eq!(program.read_buffer::<u8>("bytes").block_on().unwrap(), [1, 2, 3]);
+    assert_eq!(program.read_buffer::<i16>("shorts").block_on().unwrap(), [-1, 2, -3]);
+
+    let output = program.run_blocking::<u8>(&[], &[], &["bytes"]).unwrap();
+    assert_eq!(output, [vec![1, 2, 3]]);
+}
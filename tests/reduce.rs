@@ -0,0 +1,148 @@
+mod common;
+
+use bytemuck::Pod;
+use pollster::FutureExt;
+use wgpu::BufferUsages;
+
+use tiny_wgpu::{ComputeProgram, ElementType, Reduction};
+
+use common::{random_u32s, TestProgram};
+
+const USAGE: BufferUsages = BufferUsages::STORAGE.union(BufferUsages::COPY_SRC);
+
+/// Partial tiles, exactly one tile, and several passes
+const SIZES: [usize; 5] = [1, 1023, 1024, 1025, 1 << 20];
+
+/// Reduces `input` on the GPU and reads back the one-element result.
+fn reduce<T: Pod, R: Pod>(program: &mut TestProgram, input: &[T], element: ElementType, reduction: Reduction) -> R {
+    program.add_buffer_init("input", USAGE, input);
+    program.add_reduction("result", "input", element, reduction).unwrap();
+
+    let mut encoder = program.compute.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: None
+    });
+    program.reduce(&mut encoder, "result").unwrap();
+    program.compute.queue.submit(Some(encoder.finish()));
+
+    program.read_buffer::<R>("result").block_on().unwrap()[0]
+}
+
+/// Values within `-500..1000`. The `f32`s are small integers, so that their sums are
+/// exact in any order.
+fn values(count: usize, seed: u32) -> (Vec<u32>, Vec<i32>, Vec<f32>) {
+    let random = random_u32s(count, seed);
+    let u32s: Vec<u32> = random.iter().map(|value| value % 1000).collect();
+    let i32s: Vec<i32> = random.iter().map(|value| (value % 1000) as i32 - 500).collect();
+    let f32s: Vec<f32> = random.iter().map(|value| (value % 16) as f32 - 8.0).collect();
+    (u32s, i32s, f32s)
+}
+
+/// The index of the first element that no later element beats.
+fn first_index<T: Copy>(values: &[T], better: impl Fn(T, T) -> bool) -> u32 {
+    let mut best = 0;
+    for (i, &value) in values.iter().enumerate() {
+        if better(value, values[best]) {
+            best = i;
+        }
+    }
+    best as u32
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn sums_match_the_cpu() {
+    let mut program = common::program();
+
+    for count in SIZES {
+        let (u32s, i32s, f32s) = values(count, count as u32);
+
+        let sum: u32 = reduce(&mut program, &u32s, ElementType::U32, Reduction::Sum);
+        assert_eq!(sum, u32s.iter().sum::<u32>(), "{} u32s", count);
+
+        let sum: i32 = reduce(&mut program, &i32s, ElementType::I32, Reduction::Sum);
+        assert_eq!(sum, i32s.iter().sum::<i32>(), "{} i32s", count);
+
+        let sum: f32 = reduce(&mut program, &f32s, ElementType::F32, Reduction::Sum);
+        assert_eq!(sum, f32s.iter().sum::<f32>(), "{} f32s", count);
+    }
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn min_and_max_match_the_cpu() {
+    let mut program = common::program();
+
+    for count in SIZES {
+        let (u32s, i32s, f32s) = values(count, count as u32 + 1);
+
+        let min: u32 = reduce(&mut program, &u32s, ElementType::U32, Reduction::Min);
+        let max: u32 = reduce(&mut program, &u32s, ElementType::U32, Reduction::Max);
+        assert_eq!((min, max), (*u32s.iter().min().unwrap(), *u32s.iter().max().unwrap()), "{} u32s", count);
+
+        let min: i32 = reduce(&mut program, &i32s, ElementType::I32, Reduction::Min);
+        let max: i32 = reduce(&mut program, &i32s, ElementType::I32, Reduction::Max);
+        assert_eq!((min, max), (*i32s.iter().min().unwrap(), *i32s.iter().max().unwrap()), "{} i32s", count);
+
+        let min: f32 = reduce(&mut program, &f32s, ElementType::F32, Reduction::Min);
+        let max: f32 = reduce(&mut program, &f32s, ElementType::F32, Reduction::Max);
+        assert_eq!((min, max), (f32s.iter().copied().fold(f32::INFINITY, f32::min), f32s.iter().copied().fold(f32::NEG_INFINITY, f32::max)), "{} f32s", count);
+    }
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn arg_reductions_pick_the_first_of_equal_values() {
+    let mut program = common::program();
+
+    for count in SIZES {
+        let (mut u32s, mut i32s, mut f32s) = values(count, count as u32 + 2);
+
+        // Repeat the extremes, with the first one away from index 0 and from the start of a tile
+        let ties = [count / 2, count * 3 / 4, count - 1];
+        for &i in &ties {
+            u32s[i] = 1000;
+            i32s[i] = -1000;
+            f32s[i] = -1000.0;
+        }
+
+        let expected_max = first_index(&u32s, |a, b| a > b);
+        assert_eq!(expected_max, ties[0] as u32);
+        let index: u32 = reduce(&mut program, &u32s, ElementType::U32, Reduction::ArgMax);
+        assert_eq!(index, expected_max, "{} u32s", count);
+        let index: u32 = reduce(&mut program, &u32s, ElementType::U32, Reduction::ArgMin);
+        assert_eq!(index, first_index(&u32s, |a, b| a < b), "{} u32s", count);
+
+        let index: u32 = reduce(&mut program, &i32s, ElementType::I32, Reduction::ArgMin);
+        assert_eq!(index, ties[0] as u32, "{} i32s", count);
+        let index: u32 = reduce(&mut program, &i32s, ElementType::I32, Reduction::ArgMax);
+        assert_eq!(index, first_index(&i32s, |a, b| a > b), "{} i32s", count);
+
+        let index: u32 = reduce(&mut program, &f32s, ElementType::F32, Reduction::ArgMin);
+        assert_eq!(index, ties[0] as u32, "{} f32s", count);
+        let index: u32 = reduce(&mut program, &f32s, ElementType::F32, Reduction::ArgMax);
+        assert_eq!(index, first_index(&f32s, |a, b| a > b), "{} f32s", count);
+
+        // When every element is equal, the first one wins
+        let equal = vec![7u32; count];
+        let index: u32 = reduce(&mut program, &equal, ElementType::U32, Reduction::ArgMin);
+        assert_eq!(index, 0, "{} equal u32s", count);
+        let index: u32 = reduce(&mut program, &equal, ElementType::U32, Reduction::ArgMax);
+        assert_eq!(index, 0, "{} equal u32s", count);
+    }
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn custom_reductions_match_the_cpu() {
+    let mut program = common::program();
+    let reduction = Reduction::Custom {
+        combine: "fn combine(a: u32, b: u32) -> u32 { return a ^ b; }",
+        identity: "0u"
+    };
+
+    for count in SIZES {
+        let input = random_u32s(count, count as u32 + 3);
+        let result: u32 = reduce(&mut program, &input, ElementType::U32, reduction);
+        assert_eq!(result, input.iter().fold(0, |a, b| a ^ b), "{} u32s", count);
+    }
+}
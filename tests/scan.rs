@@ -0,0 +1,104 @@
+mod common;
+
+use bytemuck::Pod;
+use pollster::FutureExt;
+use wgpu::BufferUsages;
+
+use tiny_wgpu::{ComputeProgram, ElementType, Scan};
+
+use common::{random_u32s, TestProgram};
+
+const USAGE: BufferUsages = BufferUsages::STORAGE.union(BufferUsages::COPY_SRC);
+
+/// Partial blocks, exactly one block, two levels of block totals and three
+const SIZES: [usize; 6] = [1, 1023, 1024, 1025, 1 << 20, (1 << 20) + 1];
+
+/// Scans `input` on the GPU and reads back the result.
+fn scan<T: Pod>(program: &mut TestProgram, input: &[T], element: ElementType, scan: Scan) -> Vec<T> {
+    program.add_buffer_init("input", USAGE, input);
+    program.add_scan("output", "input", element, scan).unwrap();
+
+    let mut encoder = program.compute.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: None
+    });
+    program.scan(&mut encoder, "output").unwrap();
+    program.compute.queue.submit(Some(encoder.finish()));
+
+    program.read_buffer::<T>("output").block_on().unwrap()
+}
+
+/// The prefix sums of `input` on the CPU.
+fn expected<T: Copy + std::ops::Add<Output = T>>(input: &[T], zero: T, scan: Scan) -> Vec<T> {
+    let mut sum = zero;
+
+    input
+        .iter()
+        .map(|&value| {
+            let before = sum;
+            sum = sum + value;
+            match scan {
+                Scan::Inclusive => sum,
+                Scan::Exclusive => before
+            }
+        })
+        .collect()
+}
+
+/// The index and values of the first element where `actual` and `expected` differ.
+fn first_difference<T: PartialEq + Copy>(actual: &[T], expected: &[T]) -> Option<(usize, T, T)> {
+    assert_eq!(actual.len(), expected.len());
+    actual.iter().zip(expected).position(|(a, e)| a != e).map(|i| (i, actual[i], expected[i]))
+}
+
+fn check_all(program: &mut TestProgram, kind: Scan) {
+    for count in SIZES {
+        let random = random_u32s(count, count as u32);
+
+        // Small integers, so that `f32` sums are exact in any order
+        let u32s: Vec<u32> = random.iter().map(|value| value % 1000).collect();
+        let i32s: Vec<i32> = random.iter().map(|value| (value % 1000) as i32 - 500).collect();
+        let f32s: Vec<f32> = random.iter().map(|value| (value % 16) as f32 - 8.0).collect();
+
+        let result = scan(program, &u32s, ElementType::U32, kind);
+        assert_eq!(first_difference(&result, &expected(&u32s, 0, kind)), None, "{:?} scan of {} u32s", kind, count);
+
+        let result = scan(program, &i32s, ElementType::I32, kind);
+        assert_eq!(first_difference(&result, &expected(&i32s, 0, kind)), None, "{:?} scan of {} i32s", kind, count);
+
+        let result = scan(program, &f32s, ElementType::F32, kind);
+        assert_eq!(first_difference(&result, &expected(&f32s, 0.0, kind)), None, "{:?} scan of {} f32s", kind, count);
+    }
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn inclusive_scans_match_the_cpu() {
+    let mut program = common::program();
+    check_all(&mut program, Scan::Inclusive);
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn exclusive_scans_match_the_cpu() {
+    let mut program = common::program();
+    check_all(&mut program, Scan::Exclusive);
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn scans_can_be_recorded_again() {
+    let mut program = common::program();
+    let input = vec![1u32; 5000];
+
+    scan(&mut program, &input, ElementType::U32, Scan::Inclusive);
+
+    // Recording the scan a second time must not add the block offsets twice
+    let mut encoder = program.compute.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: None
+    });
+    program.scan(&mut encoder, "output").unwrap();
+    program.compute.queue.submit(Some(encoder.finish()));
+
+    let result = program.read_buffer::<u32>("output").block_on().unwrap();
+    assert_eq!(first_difference(&result, &(1..=5000).collect::<Vec<_>>()), None);
+}
@@ -0,0 +1,22 @@
+mod common;
+
+use wgpu::BufferUsages;
+
+use tiny_wgpu::{ComputeProgram, Error};
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn small_elements_are_written_in_aligned_runs() {
+    let mut program = common::program();
+
+    let mut bytes = program.add_gpu_vec::<u8>("bytes", BufferUsages::STORAGE, 4);
+
+    assert!(!bytes.extend(&mut program, &[1, 2, 3, 4]).unwrap());
+    assert!(matches!(bytes.push(&mut program, 5), Err(Error::Range { .. })));
+
+    // The failed push neither grew the buffer nor changed its length
+    assert_eq!((bytes.len(), bytes.capacity()), (4, 4));
+
+    assert!(bytes.extend(&mut program, &[5, 6, 7, 8]).unwrap());
+    assert_eq!(bytes.read(&program).unwrap(), [1, 2, 3, 4, 5, 6, 7, 8]);
+}